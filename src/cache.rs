@@ -0,0 +1,119 @@
+//! 本地文档缓存，用于跳过未更新的文档
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::{error::Result, report::Problem};
+
+const DOC_CACHE_FILE: &str = "docs.json";
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct DocCache {
+    #[serde(skip)]
+    path: PathBuf,
+    namespaces: HashMap<String, NamespaceCache>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct NamespaceCache {
+    pub book_id: i32,
+    pub namespace: String,
+    pub path: PathBuf,
+    pub articles: HashMap<String, PathBuf>,
+    pub docs: HashMap<u32, DocCacheEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DocCacheEntry {
+    pub updated_at: String,
+    pub hash: String,
+    pub path: PathBuf,
+    pub order: usize,
+    pub schema: Option<Value>,
+    pub output: String,
+    /// Problems found when the output was rendered
+    #[serde(default)]
+    pub problems: Vec<Problem>,
+    /// Slugs of the Yuque anchors in the document
+    #[serde(default)]
+    pub anchors: HashMap<String, String>,
+    /// Url of the document on Yuque
+    #[serde(default)]
+    pub source: String,
+    /// Hash of the config the output was rendered with
+    #[serde(default)]
+    pub fingerprint: String,
+    /// Local urls of the Yuque links in the output, empty for the broken ones
+    #[serde(default)]
+    pub links: HashMap<String, String>,
+}
+
+impl DocCache {
+    /// Load the cache from `dir`, an unreadable cache is treated as empty.
+    pub fn load(dir: impl AsRef<Path>) -> Self {
+        let path = dir.as_ref().join(DOC_CACHE_FILE);
+
+        let cache = match File::open(&path) {
+            Ok(file) => serde_json::from_reader(file).unwrap_or_else(|e| {
+                warn!("Can not read the document cache due to {}.", e);
+                DocCache::default()
+            }),
+            Err(_) => DocCache::default(),
+        };
+
+        info!("Load document cache from: {}", path.display());
+
+        Self { path, ..cache }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        serde_json::to_writer(File::create(&self.path)?, self)?;
+
+        debug!("Save document cache to: {}", self.path.display());
+
+        Ok(())
+    }
+
+    pub fn namespace(&self, ns: &str) -> Option<&NamespaceCache> {
+        self.namespaces.get(ns)
+    }
+
+    pub fn namespace_mut(&mut self, ns: &str) -> &mut NamespaceCache {
+        self.namespaces.entry(ns.to_string()).or_default()
+    }
+
+    pub fn get(&self, ns: &str, id: u32) -> Option<&DocCacheEntry> {
+        self.namespaces.get(ns).and_then(|n| n.docs.get(&id))
+    }
+
+    pub fn insert(&mut self, ns: &str, id: u32, entry: DocCacheEntry) {
+        self.namespace_mut(ns).docs.insert(id, entry);
+    }
+
+    pub fn namespaces(&self) -> impl Iterator<Item = &NamespaceCache> {
+        self.namespaces.values()
+    }
+
+    /// Drop the cached documents which no longer exist in the namespace.
+    pub fn retain(&mut self, ns: &str, ids: &HashSet<u32>) {
+        if let Some(n) = self.namespaces.get_mut(ns) {
+            n.docs.retain(|id, _| ids.contains(id));
+        }
+    }
+}
+
+pub fn hash(bytes: impl AsRef<[u8]>) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
@@ -1,8 +1,11 @@
-use std::{borrow::Cow, env, str::FromStr};
+use std::{borrow::Cow, collections::HashMap, env, str::FromStr};
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-use crate::error::{Error, Result};
+use crate::{
+    embed::{EmbedProvider, EmbedRegistry},
+    error::{Error, Result},
+};
 
 pub trait Check<T> {
     fn check(self) -> Result<T>;
@@ -15,6 +18,36 @@ pub struct Namespace<'a> {
     pub text: Cow<'a, str>,
     #[serde(default)]
     pub nav: String,
+    /// Transforms of the documents in order, the default ones if not set
+    #[serde(default)]
+    pub transforms: Option<Vec<TransformConfig>>,
+    /// Metadata of the documents written into the frontmatter
+    #[serde(default = "default_frontmatter")]
+    pub frontmatter: Vec<FrontmatterField>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum FrontmatterField {
+    Author,
+    CreatedAt,
+    UpdatedAt,
+    WordCount,
+    /// Minutes to read the document, estimated from the word count
+    ReadingTime,
+    Cover,
+    /// Url of the document on Yuque
+    Source,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TransformConfig {
+    pub name: String,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Options passed to the factory of the transform
+    #[serde(default)]
+    pub options: serde_yaml::Value,
 }
 
 #[derive(Debug, Deserialize)]
@@ -90,13 +123,39 @@ pub struct GeneratorConfig<'a> {
     pub namespaces: Vec<Namespace<'a>>,
     #[serde(default = "default_build_command")]
     pub build_command: Cow<'a, str>,
+    #[serde(default = "default_cache_dir")]
+    pub cache_dir: Cow<'a, str>,
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+    #[serde(default)]
+    pub rate_limit: u32,
+    #[serde(default)]
+    pub image: ImageConfig,
+    #[serde(default)]
+    pub sanitize: SanitizeConfig,
+    #[serde(default)]
+    pub embeds: Vec<EmbedProvider>,
+    #[serde(default)]
+    pub strict: bool,
+    #[serde(default)]
+    pub markdown: MarkdownConfig,
 }
 
 pub struct CheckedGeneratorConfig<'a> {
     pub host: Cow<'a, str>,
+    /// `base` of the site, which the raw html linking to the local assets is prefixed with
+    pub base: Cow<'a, str>,
     pub token: Cow<'a, str>,
     pub namespaces: Vec<Namespace<'a>>,
     pub build_command: Cow<'a, str>,
+    pub cache_dir: Cow<'a, str>,
+    pub concurrency: usize,
+    pub rate_limit: u32,
+    pub image: ImageConfig,
+    pub sanitize: SanitizeConfig,
+    pub embeds: EmbedRegistry,
+    pub strict: bool,
+    pub markdown: MarkdownConfig,
 }
 
 impl<'a> Check<CheckedGeneratorConfig<'a>> for GeneratorConfig<'a> {
@@ -106,6 +165,14 @@ impl<'a> Check<CheckedGeneratorConfig<'a>> for GeneratorConfig<'a> {
             token,
             namespaces,
             build_command,
+            cache_dir,
+            concurrency,
+            rate_limit,
+            image,
+            sanitize,
+            embeds,
+            strict,
+            markdown,
         } = self;
 
         let host = host
@@ -117,13 +184,144 @@ impl<'a> Check<CheckedGeneratorConfig<'a>> for GeneratorConfig<'a> {
 
         Ok(CheckedGeneratorConfig {
             host,
+            base: default_base(),
             token,
             namespaces,
             build_command,
+            cache_dir,
+            concurrency: concurrency.max(1),
+            rate_limit,
+            image: image.check()?,
+            sanitize,
+            embeds: EmbedRegistry::new(embeds)?,
+            strict,
+            markdown,
         })
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageMode {
+    /// Save the images under `docs/public/{assets}` and link to them
+    #[default]
+    Local,
+    /// Inline the images as base64 data uri
+    Base64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Keep the format of the original image
+    #[default]
+    Original,
+    Webp,
+    /// Only available with the `avif` feature
+    Avif,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ImageConfig {
+    pub mode: ImageMode,
+    /// Directory of the saved images, relative to `docs/public`
+    pub assets: String,
+    pub format: OutputFormat,
+    /// Images wider than this are scaled down
+    pub max_width: Option<u32>,
+    /// Max size of the downloaded images cache in MB
+    pub cache_size: u64,
+}
+
+impl Default for ImageConfig {
+    fn default() -> Self {
+        Self {
+            mode: ImageMode::default(),
+            assets: "assets/images".into(),
+            format: OutputFormat::default(),
+            max_width: None,
+            cache_size: 512,
+        }
+    }
+}
+
+impl Check<ImageConfig> for ImageConfig {
+    fn check(self) -> Result<ImageConfig> {
+        if self.format == OutputFormat::Avif && !cfg!(feature = "avif") {
+            return Err(Error::CantParse(
+                "image format `avif` requires the `avif` feature".into(),
+            ));
+        }
+
+        Ok(self)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SanitizeConfig {
+    pub enabled: bool,
+    /// Extra allowed tags
+    pub tags: Vec<String>,
+    /// Extra allowed attributes of the tags
+    pub attributes: HashMap<String, Vec<String>>,
+}
+
+impl Default for SanitizeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            tags: vec![],
+            attributes: HashMap::new(),
+        }
+    }
+}
+
+/// Extensions and options of the markdown parser and renderer,
+/// see the options of `comrak` for the details.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct MarkdownConfig {
+    pub strikethrough: bool,
+    pub table: bool,
+    pub autolink: bool,
+    pub tasklist: bool,
+    pub superscript: bool,
+    pub footnotes: bool,
+    pub description_lists: bool,
+    /// Convert the quotes and dashes to their typographic forms
+    pub smart: bool,
+    /// Language of the code blocks without one
+    pub default_info_string: Option<String>,
+    /// Render the soft line breaks as hard ones
+    pub hardbreaks: bool,
+    /// Wrap the output at this column, `0` to keep the lines
+    pub width: usize,
+    /// Keep the source of the blocks not changed by the transforms byte-for-byte,
+    /// instead of rendering the whole document again
+    pub passthrough: bool,
+}
+
+impl Default for MarkdownConfig {
+    fn default() -> Self {
+        Self {
+            strikethrough: true,
+            table: true,
+            autolink: true,
+            tasklist: true,
+            superscript: true,
+            footnotes: true,
+            description_lists: false,
+            smart: false,
+            default_info_string: Some("text".into()),
+            hardbreaks: false,
+            width: 0,
+            passthrough: false,
+        }
+    }
+}
+
 #[derive(Deserialize, Debug)]
 pub struct Config<'a> {
     pub site: SiteConfig<'a>,
@@ -133,8 +331,14 @@ pub struct Config<'a> {
 impl<'a> Check<(CheckedSiteConfig<'a>, CheckedGeneratorConfig<'a>)> for Config<'a> {
     fn check(self) -> Result<(CheckedSiteConfig<'a>, CheckedGeneratorConfig<'a>)> {
         let Config { site, generator } = self;
+        let site = site.check()?;
+
+        let generator = CheckedGeneratorConfig {
+            base: site.base.clone(),
+            ..generator.check()?
+        };
 
-        Ok((site.check()?, generator.check()?))
+        Ok((site, generator))
     }
 }
 
@@ -153,3 +357,27 @@ fn default_host<'a>() -> Cow<'a, str> {
 fn default_build_command<'a>() -> Cow<'a, str> {
     "npm run docs:build".into()
 }
+
+fn default_cache_dir<'a>() -> Cow<'a, str> {
+    "cache".into()
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_frontmatter() -> Vec<FrontmatterField> {
+    vec![
+        FrontmatterField::Author,
+        FrontmatterField::CreatedAt,
+        FrontmatterField::UpdatedAt,
+        FrontmatterField::WordCount,
+        FrontmatterField::ReadingTime,
+        FrontmatterField::Cover,
+        FrontmatterField::Source,
+    ]
+}
+
+fn default_concurrency() -> usize {
+    4
+}
@@ -0,0 +1,513 @@
+use log::debug;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use regex::Regex;
+use scraper::{ElementRef, Html, Node};
+use serde_json::Value;
+
+use super::{
+    escape,
+    table::{render_table, Cell},
+};
+
+/// Characters which would end the destination of a markdown link or image.
+const DESTINATION: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'(')
+    .add(b')')
+    .add(b'<')
+    .add(b'>')
+    .add(b'[')
+    .add(b']')
+    .add(b'\\')
+    .add(b'`');
+
+/// Whether the body is in the lake format of the Yuque editor instead of markdown.
+pub fn is_lake(body: &str) -> bool {
+    let head = body.trim_start();
+
+    head.get(..15)
+        .is_some_and(|s| s.eq_ignore_ascii_case("<!doctype lake>"))
+        || head.starts_with(r#"<meta name="doc-version""#)
+}
+
+/// Convert the lake document into markdown, the cards which can not be expressed
+/// in markdown are skipped.
+pub fn lake_to_markdown(body: &str) -> String {
+    let html = Html::parse_fragment(body);
+
+    let mut markdown = String::new();
+    blocks(html.root_element(), &mut markdown);
+
+    let blank_lines = Regex::new(r"\n{3,}").unwrap();
+    let mut markdown = blank_lines
+        .replace_all(markdown.trim(), "\n\n")
+        .into_owned();
+    markdown.push('\n');
+
+    markdown
+}
+
+fn blocks(element: ElementRef, out: &mut String) {
+    for child in element.children() {
+        match child.value() {
+            Node::Text(text) if !text.trim().is_empty() => {
+                out.push_str(&escape(text));
+                out.push_str("\n\n");
+            }
+            Node::Element(_) => block(ElementRef::wrap(child).unwrap(), out),
+            _ => (),
+        }
+    }
+}
+
+fn block(element: ElementRef, out: &mut String) {
+    let name = element.value().name();
+
+    match name {
+        "meta" | "colgroup" | "style" | "script" => (),
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+            let level = name[1..].parse::<usize>().unwrap();
+            out.push_str(&format!("{} {}\n\n", "#".repeat(level), inline(element)));
+        }
+        "p" => {
+            out.push_str(&inline(element));
+            out.push_str("\n\n");
+        }
+        "blockquote" => {
+            let mut quote = String::new();
+            blocks(element, &mut quote);
+
+            for line in quote.trim().lines() {
+                out.push_str(format!("> {}", line).trim_end());
+                out.push('\n');
+            }
+            out.push('\n');
+        }
+        "ul" | "ol" => {
+            list(element, 0, out);
+            out.push('\n');
+        }
+        "pre" => {
+            let code = element.text().collect::<String>();
+            out.push_str(&code_block("", &code));
+        }
+        "hr" => out.push_str("---\n\n"),
+        "table" => {
+            out.push_str(&table(element));
+            out.push('\n');
+        }
+        "card" => {
+            out.push_str(&card(element));
+            out.push_str("\n\n");
+        }
+        "div" | "alert" => match alert_type(element) {
+            Some(kind) => {
+                out.push_str(&format!(":::{}\n", kind));
+                blocks(element, out);
+                out.push_str(":::\n\n");
+            }
+            None => blocks(element, out),
+        },
+        _ => {
+            out.push_str(&inline(element));
+            out.push_str("\n\n");
+        }
+    }
+}
+
+/// Type of the callout, such as `tips` and `warning`.
+fn alert_type(element: ElementRef) -> Option<&str> {
+    let is_alert = element.value().name() == "alert"
+        || element
+            .value()
+            .classes()
+            .any(|class| class.contains("alert"));
+
+    if !is_alert {
+        return None;
+    }
+
+    element.value().attr("data-type")
+}
+
+/// Lake keeps the lists flat and nests them by `data-lake-indent`.
+fn list(element: ElementRef, depth: usize, out: &mut String) {
+    let ordered = element.value().name() == "ol";
+    let indent = element
+        .value()
+        .attr("data-lake-indent")
+        .and_then(|i| i.parse::<usize>().ok())
+        .unwrap_or_default()
+        + depth;
+    let start = element
+        .value()
+        .attr("start")
+        .and_then(|i| i.parse::<usize>().ok())
+        .unwrap_or(1);
+
+    let items = element
+        .children()
+        .filter_map(ElementRef::wrap)
+        .filter(|e| e.value().name() == "li");
+
+    for (i, item) in items.enumerate() {
+        let marker = if ordered {
+            format!("{}. ", start + i)
+        } else {
+            "- ".into()
+        };
+
+        let mut content = String::new();
+        let mut nested = String::new();
+
+        for child in item.children() {
+            match ElementRef::wrap(child) {
+                Some(e) if matches!(e.value().name(), "ul" | "ol") => {
+                    list(e, indent + 1, &mut nested)
+                }
+                Some(e) if e.value().name() == "p" => {
+                    if !content.is_empty() {
+                        content.push_str("<br />");
+                    }
+                    content.push_str(&inline(e));
+                }
+                Some(e) => content.push_str(&inline_element(e)),
+                None => {
+                    if let Node::Text(text) = child.value() {
+                        content.push_str(&escape(text));
+                    }
+                }
+            }
+        }
+
+        out.push_str(&format!(
+            "{}{}{}\n",
+            "  ".repeat(indent),
+            marker,
+            content.trim()
+        ));
+        out.push_str(&nested);
+    }
+}
+
+fn table(element: ElementRef) -> String {
+    let rows = element
+        .descendants()
+        .filter_map(ElementRef::wrap)
+        .filter(|e| e.value().name() == "tr")
+        .map(|row| {
+            row.children()
+                .filter_map(ElementRef::wrap)
+                .filter(|e| matches!(e.value().name(), "td" | "th"))
+                .map(|cell| {
+                    let span = |name| {
+                        cell.value()
+                            .attr(name)
+                            .and_then(|s| s.parse::<usize>().ok())
+                            .unwrap_or(1)
+                    };
+
+                    let content = cell
+                        .children()
+                        .filter_map(|child| match ElementRef::wrap(child) {
+                            Some(e) if e.value().name() == "p" => Some(inline(e)),
+                            Some(e) => Some(inline_element(e)),
+                            None => match child.value() {
+                                Node::Text(text) => Some(escape(text)),
+                                _ => None,
+                            },
+                        })
+                        .filter(|s| !s.trim().is_empty())
+                        .collect::<Vec<_>>()
+                        .join("<br />");
+
+                    Cell {
+                        content,
+                        colspan: span("colspan"),
+                        rowspan: span("rowspan"),
+                    }
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    render_table(&rows)
+}
+
+fn inline(element: ElementRef) -> String {
+    element
+        .children()
+        .map(|child| match child.value() {
+            Node::Text(text) => escape(&text.replace('\n', " ")),
+            Node::Element(_) => inline_element(ElementRef::wrap(child).unwrap()),
+            _ => String::new(),
+        })
+        .collect()
+}
+
+fn inline_element(element: ElementRef) -> String {
+    let content = || inline(element);
+
+    match element.value().name() {
+        "strong" | "b" => wrap("**", &content()),
+        "em" | "i" => wrap("*", &content()),
+        "del" | "s" => wrap("~~", &content()),
+        "code" => {
+            let code = element.text().collect::<String>();
+
+            if code.contains('`') {
+                format!("`` {} ``", code)
+            } else {
+                format!("`{}`", code)
+            }
+        }
+        "u" | "sup" | "sub" | "mark" => {
+            let tag = element.value().name();
+            format!("<{}>{}</{}>", tag, content(), tag)
+        }
+        "a" => match element.value().attr("href") {
+            Some(href) => link(&content(), href),
+            None => content(),
+        },
+        "img" => image(
+            element.value().attr("alt").unwrap_or_default(),
+            element.value().attr("src").unwrap_or_default(),
+        ),
+        "br" => "<br />".into(),
+        "card" => card(element),
+        _ => content(),
+    }
+}
+
+fn card(element: ElementRef) -> String {
+    let name = element.value().attr("name").unwrap_or_default();
+    let value = card_value(element);
+    let field = |key: &str| value[key].as_str().unwrap_or_default().to_string();
+
+    match name {
+        "image" => image(&field("name"), &field("src")),
+        "codeblock" => code_block(&field("mode"), &field("code")),
+        "hr" => "---".into(),
+        // Left to the `diagram` transform, which knows the languages of the theme
+        "diagram" => {
+            let params = url::form_urlencoded::Serializer::new(String::new())
+                .append_pair("card", "diagram")
+                .append_pair("type", &field("type"))
+                .append_pair("code", &field("code"))
+                .finish();
+            image("", &format!("{}#{}", field("url"), params))
+        }
+        // Left to the `math` transform as well, markdown would escape the LaTeX
+        "math" => {
+            let params = url::form_urlencoded::Serializer::new(String::new())
+                .append_pair("card", "math")
+                .append_pair("code", field("code").trim())
+                .finish();
+            image("", &format!("{}#{}", field("url"), params))
+        }
+        "file" => link(&escape(&field("name")), &field("src")),
+        "checkbox" if value.as_bool().unwrap_or_default() => "[x] ".into(),
+        "checkbox" => "[ ] ".into(),
+        "label" => escape(&field("label")),
+        "mention" => format!("@{}", escape(&field("name"))),
+        "yuque" | "yuqueinline" => {
+            let title = value["detail"]["title"].as_str().unwrap_or("");
+            link(&escape(title), &field("url"))
+        }
+        "bookmarklink" | "bookmarkInline" => {
+            let title = value["detail"]["title"].as_str().unwrap_or_default();
+            link(&escape(title), &field("src"))
+        }
+        _ => {
+            debug!("Skip lake card: {}", name);
+
+            match value["src"].as_str().or_else(|| value["url"].as_str()) {
+                Some(src) => link(&escape(&field("name")), src),
+                None => String::new(),
+            }
+        }
+    }
+}
+
+/// Value of the card is the url encoded json prefixed with `data:`.
+fn card_value(element: ElementRef) -> Value {
+    element
+        .value()
+        .attr("value")
+        .and_then(|value| value.strip_prefix("data:"))
+        .and_then(|value| {
+            let json = percent_decode_str(value).decode_utf8_lossy();
+            serde_json::from_str(&json).ok()
+        })
+        .unwrap_or_default()
+}
+
+fn code_block(lang: &str, code: &str) -> String {
+    let fence = if code.contains("```") { "````" } else { "```" };
+
+    format!(
+        "{}{}\n{}\n{}\n\n",
+        fence,
+        lang,
+        code.trim_end_matches('\n'),
+        fence
+    )
+}
+
+/// Link of the markdown `text`, only the text is kept when the url is unsafe.
+fn link(text: &str, url: &str) -> String {
+    match destination(url) {
+        Some(url) => format!("[{}]({})", text, url),
+        None => text.to_string(),
+    }
+}
+
+/// Image of the plain `alt`, which is dropped when the url is unsafe.
+fn image(alt: &str, src: &str) -> String {
+    match destination(src) {
+        Some(src) => format!("![{}]({})", escape(alt), src),
+        None => String::new(),
+    }
+}
+
+/// Destination of the markdown link, `None` for the schemes which can run scripts,
+/// such as `javascript:`. The browsers ignore the whitespaces in the scheme.
+fn destination(url: &str) -> Option<String> {
+    let url = url.trim();
+    let compact = url
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect::<String>()
+        .to_ascii_lowercase();
+
+    let scheme = compact
+        .split_once(':')
+        .map(|(scheme, _)| scheme)
+        .filter(|scheme| !scheme.contains(['/', '?', '#']));
+
+    if scheme.is_some_and(|scheme| !matches!(scheme, "http" | "https" | "mailto" | "tel")) {
+        debug!("Skip unsafe url: {}", url);
+        return None;
+    }
+
+    Some(utf8_percent_encode(url, DESTINATION).to_string())
+}
+
+fn wrap(mark: &str, content: &str) -> String {
+    if content.trim().is_empty() {
+        return content.to_string();
+    }
+
+    format!("{}{}{}", mark, content, mark)
+}
+
+#[cfg(test)]
+mod tests {
+    use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+
+    use super::*;
+
+    fn lake_card(name: &str, value: &str) -> String {
+        format!(
+            r#"<card name="{}" value="data:{}"></card>"#,
+            name,
+            utf8_percent_encode(value, NON_ALPHANUMERIC)
+        )
+    }
+
+    #[test]
+    fn lake_format() {
+        assert!(is_lake("<!doctype lake><p>text</p>"));
+        assert!(is_lake(
+            r#"<meta name="doc-version" content="1" /><p>text</p>"#
+        ));
+        assert!(!is_lake("# Markdown"));
+    }
+
+    #[test]
+    fn blocks() {
+        let markdown = lake_to_markdown(
+            "<!doctype lake><h2>Title</h2><p>Some <strong>bold</strong> and a_b</p>\
+             <ul><li>one</li><li>two</li></ul><ul data-lake-indent=\"1\"><li>nested</li></ul>\
+             <blockquote><p>quote</p></blockquote>",
+        );
+
+        assert!(markdown.starts_with("## Title\n\nSome **bold** and a\\_b\n\n"));
+        assert!(markdown.contains("- one\n- two\n"));
+        assert!(markdown.contains("  - nested\n"));
+        assert!(markdown.contains("> quote\n"));
+    }
+
+    #[test]
+    fn math_cards() {
+        let value =
+            r#"{"code":"x_1 + \\frac{a}{b}","url":"https://cdn.nlark.com/yuque/__latex/a.svg"}"#;
+        let markdown = lake_to_markdown(&format!(
+            "<!doctype lake>{}<p>inline {}</p>",
+            lake_card("math", value),
+            lake_card("math", value)
+        ));
+
+        let image = "![](https://cdn.nlark.com/yuque/__latex/a.svg#card=math&code=x_1+%2B+%5Cfrac%7Ba%7D%7Bb%7D)";
+
+        assert!(markdown.starts_with(&format!("{}\n\n", image)));
+        assert!(markdown.contains(&format!("inline {}", image)));
+    }
+
+    #[test]
+    fn code_cards() {
+        let markdown = lake_to_markdown(&format!(
+            "<!doctype lake>{}",
+            lake_card("codeblock", r#"{"mode":"rust","code":"fn main() {}\n"}"#)
+        ));
+
+        assert_eq!(markdown, "```rust\nfn main() {}\n```\n");
+    }
+
+    #[test]
+    fn link_destinations() {
+        let markdown = lake_to_markdown(
+            r#"<!doctype lake><p><a href="https://example.com/a_(b) c">link</a></p>"#,
+        );
+
+        assert_eq!(markdown, "[link](https://example.com/a_%28b%29%20c)\n");
+    }
+
+    #[test]
+    fn image_alts() {
+        let markdown = lake_to_markdown(&format!(
+            r#"<!doctype lake><p><img alt="a](x)" src="https://example.com/a.png"></p>{}"#,
+            lake_card(
+                "image",
+                r#"{"name":"b]","src":"https://example.com/b).png"}"#
+            )
+        ));
+
+        assert_eq!(
+            markdown,
+            "![a\\](x)](https://example.com/a.png)\n\n![b\\]](https://example.com/b%29.png)\n"
+        );
+    }
+
+    #[test]
+    fn unsafe_links() {
+        let markdown = lake_to_markdown(&format!(
+            r#"<!doctype lake><p><a href=" java&#9;script:alert(1)">text</a></p><p><img src="javascript:alert(1)" alt="x"></p>{}"#,
+            lake_card("file", r#"{"name":"file.pdf","src":"JavaScript:alert(1)"}"#)
+        ));
+
+        assert_eq!(markdown, "text\n\nfile.pdf\n");
+    }
+
+    #[test]
+    fn merged_table() {
+        let markdown = lake_to_markdown(
+            "<!doctype lake><table><tr><td colspan=\"2\"><p>a</p></td></tr>\
+             <tr><td><p>b</p></td><td><p>c</p></td></tr></table>",
+        );
+
+        assert!(markdown.contains(r#"<td colspan="2">"#));
+        assert!(markdown.contains("\n\nb\n\n</td>"));
+    }
+}
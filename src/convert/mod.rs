@@ -0,0 +1,24 @@
+//! 将语雀中不是 markdown 的文档转换为 markdown
+
+mod lake;
+mod sheet;
+mod table;
+
+pub use self::{
+    lake::{is_lake, lake_to_markdown},
+    sheet::{is_sheet, sheet_to_markdown},
+    table::{render_table, Cell},
+};
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        if matches!(c, '\\' | '*' | '_' | '`' | '[' | ']' | '<' | '>') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
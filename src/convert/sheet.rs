@@ -0,0 +1,236 @@
+use std::collections::{HashMap, HashSet};
+
+use serde_json::Value;
+
+use super::{
+    escape,
+    table::{render_table, Cell},
+};
+use crate::error::{Error, Result};
+
+/// Whether the document is a Yuque sheet by its `format`, whose body is json instead of markdown.
+pub fn is_sheet(format: &str) -> bool {
+    format == "lakesheet"
+}
+
+/// Convert each tab of the sheet into a table under a heading of its name.
+pub fn sheet_to_markdown(body: &str) -> Result<String> {
+    let value = serde_json::from_str::<Value>(body)?;
+
+    let sheets = sheets(&value)
+        .ok_or_else(|| Error::CantParse("sheet document, no tabs are found".into()))?;
+
+    let mut markdown = String::new();
+
+    for (i, sheet) in sheets.iter().enumerate() {
+        let name = sheet["name"]
+            .as_str()
+            .map(String::from)
+            .unwrap_or_else(|| format!("Sheet{}", i + 1));
+
+        markdown.push_str(&format!("## {}\n\n", escape(&name)));
+
+        let rows = rows(sheet);
+
+        if rows.is_empty() {
+            continue;
+        }
+
+        markdown.push_str(&render_table(&rows));
+        markdown.push('\n');
+    }
+
+    Ok(markdown)
+}
+
+/// The tabs of the sheet, which may be nested as a json string in `sheet`.
+///
+/// Yuque compresses the nested string with lz-string, the plain json is kept as it is.
+fn sheets(value: &Value) -> Option<Vec<Value>> {
+    match value {
+        Value::Array(sheets)
+            if !sheets.is_empty() && sheets.iter().all(|s| s.get("data").is_some()) =>
+        {
+            Some(sheets.clone())
+        }
+        Value::Object(object) => match object.get("sheet")? {
+            Value::String(sheet) => sheets(&serde_json::from_str(&decompress(sheet)?).ok()?),
+            sheet => sheets(sheet),
+        },
+        _ => None,
+    }
+}
+
+fn decompress(sheet: &str) -> Option<String> {
+    if sheet.trim_start().starts_with(['[', '{']) {
+        return Some(sheet.to_string());
+    }
+
+    lz_str::decompress_from_base64(sheet).and_then(|s| String::from_utf16(&s).ok())
+}
+
+/// Cells keyed by the row and the column, which are the keys of `data`.
+fn rows(sheet: &Value) -> Vec<Vec<Cell>> {
+    let mut cells = HashMap::new();
+
+    for (row, columns) in sheet["data"].as_object().into_iter().flatten() {
+        for (column, cell) in columns.as_object().into_iter().flatten() {
+            if let (Ok(row), Ok(column)) = (row.parse::<usize>(), column.parse::<usize>()) {
+                cells.insert((row, column), Cell::new(escape(&cell_text(cell))));
+            }
+        }
+    }
+
+    let mut covered = HashSet::new();
+
+    for (row, column, height, width) in merges(sheet) {
+        let (height, width) = (height.max(1), width.max(1));
+
+        let cell = cells.entry((row, column)).or_default();
+        cell.rowspan = height;
+        cell.colspan = width;
+
+        for r in row..row + height {
+            for c in column..column + width {
+                if (r, c) != (row, column) {
+                    covered.insert((r, c));
+                }
+            }
+        }
+    }
+
+    // Trailing empty rows and columns are dropped
+    let filled = cells
+        .iter()
+        .filter(|(_, cell)| !cell.content.is_empty() || cell.colspan > 1 || cell.rowspan > 1)
+        .map(|(key, _)| *key);
+
+    let (Some(max_row), Some(max_column)) = (
+        filled.clone().map(|(r, _)| r).max(),
+        filled.map(|(_, c)| c).max(),
+    ) else {
+        return vec![];
+    };
+
+    (0..=max_row)
+        .map(|r| {
+            (0..=max_column)
+                .filter(|c| !covered.contains(&(r, *c)))
+                .map(|c| cells.remove(&(r, c)).unwrap_or_default())
+                .collect()
+        })
+        .collect()
+}
+
+/// Merged cells as `(row, column, rows, columns)`.
+fn merges(sheet: &Value) -> Vec<(usize, usize, usize, usize)> {
+    let merges: Vec<&Value> = match &sheet["mergeCells"] {
+        Value::Object(merges) => merges.values().collect(),
+        Value::Array(merges) => merges.iter().collect(),
+        _ => vec![],
+    };
+
+    merges
+        .into_iter()
+        .filter_map(|merge| {
+            let field = |keys: &[&str]| {
+                keys.iter()
+                    .find_map(|key| merge[*key].as_u64())
+                    .map(|v| v as usize)
+            };
+
+            Some((
+                field(&["row", "r"])?,
+                field(&["col", "c"])?,
+                field(&["rowCount", "rowspan", "rs"]).unwrap_or(1),
+                field(&["colCount", "colspan", "cs"]).unwrap_or(1),
+            ))
+        })
+        .collect()
+}
+
+/// Displayed text of the cell, the formatted `m` is preferred over the raw value `v`.
+fn cell_text(cell: &Value) -> String {
+    let value = match cell {
+        Value::Object(cell) => cell.get("m").or_else(|| cell.get("v")),
+        value => Some(value),
+    };
+
+    match value {
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Number(n)) => n.to_string(),
+        Some(Value::Bool(b)) => b.to_string(),
+        // Rich text
+        Some(Value::Object(object)) => object
+            .get("text")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string(),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn sheet() -> Value {
+        json!({
+            "name": "Prices",
+            "data": {
+                "0": { "0": { "v": "item" }, "1": { "v": "price" } },
+                "1": { "0": { "m": "apple*" }, "1": { "v": 1.5 } },
+                "2": { "0": "pear" },
+            },
+        })
+    }
+
+    #[test]
+    fn sheet_format() {
+        assert!(is_sheet("lakesheet"));
+        assert!(!is_sheet("lake"));
+        assert!(!is_sheet("markdown"));
+    }
+
+    #[test]
+    fn nested_sheet() {
+        let body = json!({ "sheet": json!([sheet()]).to_string() }).to_string();
+
+        assert_eq!(
+            sheet_to_markdown(&body).unwrap(),
+            "## Prices\n\n| item | price |\n| --- | --- |\n| apple\\* | 1.5 |\n| pear |  |\n\n"
+        );
+    }
+
+    #[test]
+    fn merged_cells() {
+        let mut sheet = sheet();
+        sheet["mergeCells"] =
+            json!({ "0_0": { "row": 0, "col": 0, "rowCount": 1, "colCount": 2 } });
+
+        let markdown = sheet_to_markdown(&json!([sheet]).to_string()).unwrap();
+
+        assert!(markdown.contains(r#"<td colspan="2">"#));
+        assert!(!markdown.contains("price"));
+    }
+
+    #[test]
+    fn compressed_sheet() {
+        // The body of a lakesheet document, `sheet` is compressed by Yuque
+        let body = r#"{"format":"lakesheet","version":"3.1.0","sheet":"NobwRAdghgtgpmAXGACgJwJYGM4GcwA0YAJlAC5RLgAMVYti4AbkmBmXDGAL5ECMdFsgAOmHD15gBjenS7Iow4QBsEk6cyR8AdAFZuB7gF0gA==="}"#;
+
+        assert_eq!(
+            sheet_to_markdown(body).unwrap(),
+            "## Prices\n\n| item | price |\n| --- | --- |\n| apple | 1.5 |\n\n"
+        );
+    }
+
+    #[test]
+    fn unsupported_sheets() {
+        assert!(sheet_to_markdown(r#"{"sheet":"not compressed"}"#).is_err());
+        assert!(sheet_to_markdown("[]").is_err());
+        assert!(sheet_to_markdown("{}").is_err());
+    }
+}
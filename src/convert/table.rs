@@ -0,0 +1,127 @@
+/// A cell of the table, whose content is inline markdown.
+#[derive(Debug, Clone)]
+pub struct Cell {
+    pub content: String,
+    pub colspan: usize,
+    pub rowspan: usize,
+}
+
+impl Cell {
+    pub fn new(content: impl Into<String>) -> Self {
+        Self {
+            content: content.into(),
+            colspan: 1,
+            rowspan: 1,
+        }
+    }
+
+    fn is_merged(&self) -> bool {
+        self.colspan > 1 || self.rowspan > 1
+    }
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self::new("")
+    }
+}
+
+/// Render the rows as a markdown table with the first row as the header,
+/// or as a html table when there are merged cells, which markdown can not express.
+pub fn render_table(rows: &[Vec<Cell>]) -> String {
+    if rows.is_empty() {
+        return String::new();
+    }
+
+    if rows.iter().flatten().any(Cell::is_merged) {
+        return render_html(rows);
+    }
+
+    let columns = rows.iter().map(Vec::len).max().unwrap_or_default();
+
+    let line = |row: &[Cell]| {
+        let cells = (0..columns)
+            .map(|i| {
+                row.get(i)
+                    .map(|c| escape_cell(&c.content))
+                    .unwrap_or_default()
+            })
+            .collect::<Vec<_>>();
+        format!("| {} |\n", cells.join(" | "))
+    };
+
+    let mut table = line(&rows[0]);
+    table.push_str(&format!("|{}\n", " --- |".repeat(columns)));
+
+    for row in &rows[1..] {
+        table.push_str(&line(row));
+    }
+
+    table
+}
+
+fn render_html(rows: &[Vec<Cell>]) -> String {
+    let mut table = String::from("<table>\n");
+
+    for row in rows {
+        table.push_str("<tr>");
+
+        for cell in row {
+            table.push_str("<td");
+            if cell.colspan > 1 {
+                table.push_str(&format!(r#" colspan="{}""#, cell.colspan));
+            }
+            if cell.rowspan > 1 {
+                table.push_str(&format!(r#" rowspan="{}""#, cell.rowspan));
+            }
+            // Blank lines around the content so the markdown inside is rendered
+            table.push_str(&format!(">\n\n{}\n\n</td>", cell.content.trim()));
+        }
+
+        table.push_str("</tr>\n");
+    }
+
+    table.push_str("</table>\n");
+    table
+}
+
+fn escape_cell(content: &str) -> String {
+    content.trim().replace('|', r"\|").replace('\n', "<br />")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn markdown_table() {
+        let rows = vec![
+            vec![Cell::new("name"), Cell::new("value")],
+            vec![Cell::new("a|b"), Cell::new("line\nbreak")],
+            vec![Cell::new("short")],
+        ];
+
+        assert_eq!(
+            render_table(&rows),
+            "| name | value |\n| --- | --- |\n| a\\|b | line<br />break |\n| short |  |\n"
+        );
+    }
+
+    #[test]
+    fn merged_cells() {
+        let mut merged = Cell::new("a");
+        merged.rowspan = 2;
+
+        let html = render_table(&[vec![merged, Cell::new("b")], vec![Cell::new("c")]]);
+
+        assert_eq!(
+            html,
+            "<table>\n<tr><td rowspan=\"2\">\n\na\n\n</td><td>\n\nb\n\n</td></tr>\n<tr><td>\n\nc\n\n</td></tr>\n</table>\n"
+        );
+    }
+
+    #[test]
+    fn empty_table() {
+        assert_eq!(render_table(&[]), "");
+    }
+}
@@ -0,0 +1,179 @@
+//! 将外部链接转换为嵌入的 iframe
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result},
+    sanitize::escape_attribute,
+    CODEPEN_IFRAME, EMBED_IFRAME,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EmbedProvider {
+    pub name: String,
+    /// Regex matched against the whole link
+    pub pattern: String,
+    /// Url of the embed, `$1` or `${name}` for the captures of `pattern`,
+    /// `{url}` for the link and `{encoded}` for the url-encoded link
+    #[serde(default = "default_embed_url")]
+    pub url: String,
+    /// Html of the embed, `{}` is replaced with the url of the embed escaped for an attribute
+    #[serde(default = "default_embed_template")]
+    pub template: String,
+}
+
+impl EmbedProvider {
+    fn new(name: &str, pattern: &str, url: &str, template: &str) -> Self {
+        Self {
+            name: name.into(),
+            pattern: pattern.into(),
+            url: url.into(),
+            template: template.into(),
+        }
+    }
+}
+
+pub struct EmbedRegistry {
+    providers: Vec<(EmbedProvider, Regex)>,
+}
+
+impl EmbedRegistry {
+    /// The custom providers take precedence over the built-in ones.
+    pub fn new(custom: Vec<EmbedProvider>) -> Result<Self> {
+        let providers = custom
+            .into_iter()
+            .chain(builtin_providers())
+            .map(|provider| {
+                let regex = Regex::new(&provider.pattern).map_err(|e| {
+                    Error::CantParse(format!("pattern of embed `{}`: {}", provider.name, e))
+                })?;
+                Ok((provider, regex))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self { providers })
+    }
+
+    pub fn providers(&self) -> impl Iterator<Item = &EmbedProvider> {
+        self.providers.iter().map(|(provider, _)| provider)
+    }
+
+    /// The html of the embed if the link matches any provider.
+    pub fn embed(&self, link: &str) -> Option<(&str, String)> {
+        self.providers.iter().find_map(|(provider, regex)| {
+            let caps = regex.captures(link)?;
+
+            let mut url = String::new();
+            caps.expand(&provider.url, &mut url);
+
+            let encoded = url::form_urlencoded::byte_serialize(link.as_bytes()).collect::<String>();
+            let url = url.replace("{url}", link).replace("{encoded}", &encoded);
+
+            Some((
+                provider.name.as_str(),
+                provider.template.replace("{}", &escape_attribute(&url)),
+            ))
+        })
+    }
+}
+
+fn builtin_providers() -> Vec<EmbedProvider> {
+    vec![
+        EmbedProvider::new(
+            "codepen",
+            r"^https?://([\w-]+\.)*codepen\.io/",
+            "{url}",
+            CODEPEN_IFRAME,
+        ),
+        EmbedProvider::new(
+            "bilibili",
+            r"^https?://(?:www\.|m\.)?bilibili\.com/video/(BV[0-9A-Za-z]+)",
+            "https://player.bilibili.com/player.html?bvid=$1&autoplay=0",
+            EMBED_IFRAME,
+        ),
+        EmbedProvider::new(
+            "youtube",
+            r"^https?://(?:(?:www\.|m\.)?youtube\.com/watch\?(?:.*&)?v=|youtu\.be/)([\w-]+)",
+            "https://www.youtube.com/embed/$1",
+            EMBED_IFRAME,
+        ),
+        EmbedProvider::new(
+            "figma",
+            r"^https?://(?:www\.)?figma\.com/(?:file|design|proto|board)/",
+            "https://www.figma.com/embed?embed_host=share&url={encoded}",
+            EMBED_IFRAME,
+        ),
+        EmbedProvider::new(
+            "codesandbox",
+            r"^https?://codesandbox\.io/(?:s|p/sandbox)/([\w-]+)",
+            "https://codesandbox.io/embed/$1",
+            EMBED_IFRAME,
+        ),
+        EmbedProvider::new(
+            "gist",
+            r"^https?://gist\.github\.com/([\w-]+)/([0-9a-f]+)",
+            "https://gist.github.com/$1/$2.js",
+            // The theme can not render `<script>`, load it inside the iframe
+            r#"<iframe height="400" style="width: 100%; border: none;" srcdoc="&lt;script src=&quot;{}&quot;&gt;&lt;/script&gt;"></iframe>
+"#,
+        ),
+    ]
+}
+
+fn default_embed_url() -> String {
+    "{url}".into()
+}
+
+fn default_embed_template() -> String {
+    EMBED_IFRAME.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_embed() {
+        let registry = EmbedRegistry::new(vec![]).unwrap();
+        let (name, html) = registry
+            .embed("https://www.bilibili.com/video/BV1xx411c7mD")
+            .unwrap();
+
+        assert_eq!(name, "bilibili");
+        assert!(html.contains(
+            r#"src="https://player.bilibili.com/player.html?bvid=BV1xx411c7mD&amp;autoplay=0""#
+        ));
+        assert!(registry.embed("https://example.com/").is_none());
+    }
+
+    #[test]
+    fn escape_link() {
+        let registry = EmbedRegistry::new(vec![]).unwrap();
+        let (_, html) = registry
+            .embed(r#"https://codepen.io/a"onload='alert(1)'<b>"#)
+            .unwrap();
+
+        assert!(html.contains(r#"https://codepen.io/a&quot;onload=&#39;alert(1)&#39;&lt;b&gt;"#));
+        assert!(!html.contains(r#"a"onload"#));
+    }
+
+    #[test]
+    fn custom_provider() {
+        let registry = EmbedRegistry::new(vec![EmbedProvider {
+            name: "video".into(),
+            pattern: r"^https://video\.example\.com/(?P<id>\d+)".into(),
+            url: "https://video.example.com/embed/${id}?from={encoded}".into(),
+            template: r#"<iframe src="{}"></iframe>"#.into(),
+        }])
+        .unwrap();
+
+        let (name, html) = registry.embed("https://video.example.com/42").unwrap();
+
+        assert_eq!(name, "video");
+        assert_eq!(
+            html,
+            r#"<iframe src="https://video.example.com/embed/42?from=https%3A%2F%2Fvideo.example.com%2F42"></iframe>"#
+        );
+    }
+}
@@ -34,6 +34,12 @@ pub enum Error {
     Image(String),
     #[error("Can not fetch the theme repo")]
     CantFetchTheme,
+    #[error("Can not download the attachment {0}")]
+    Attachment(String),
+    #[error("No such document: {0}")]
+    BrokenLink(String),
+    #[error("Found {0} problems in strict mode, see `report.json`")]
+    Strict(usize),
 }
 
 impl From<std::io::Error> for Error {
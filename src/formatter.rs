@@ -1,19 +1,61 @@
 #![allow(unused)]
 
-use std::io::Write;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    io::Write,
+    mem::{discriminant, Discriminant},
+    ops::Range,
+    ptr,
+};
 
-use crate::error::Result;
-use comrak::{format_commonmark, nodes::AstNode, parse_document, Arena, ComrakOptions};
-use log::warn;
+use crate::{
+    config::MarkdownConfig,
+    error::{Error, Result},
+    transform::{Transform, TransformContext},
+};
+use comrak::{
+    format_commonmark,
+    nodes::{AstNode, NodeValue},
+    parse_document, Arena, ComrakOptions,
+};
+use futures_util::{stream, FutureExt, StreamExt};
+use log::{debug, warn};
 
 pub type FormatFunction<'a> = fn(&'a AstNode<'a>) -> Result<()>;
 pub type FormatFunctionWithArgs<'a, Arg> = fn(&'a AstNode<'a>, &'a Arg) -> Result<()>;
 
+/// A failed transform on a node.
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub transform: String,
+    /// Line of the node in the source, `0` if unknown
+    pub line: u32,
+    pub document: String,
+    pub error: Error,
+}
+
+/// A block before the transforms.
+struct Block<'a> {
+    node: &'a AstNode<'a>,
+    /// Lines in the source, including the blank lines and the reference definitions after it
+    lines: Range<usize>,
+    /// Children of the containers in the source order
+    children: Vec<&'a AstNode<'a>>,
+    kind: Discriminant<NodeValue>,
+    rendered: Vec<u8>,
+}
+
+type Blocks<'a> = HashMap<*const AstNode<'a>, Block<'a>>;
+
 pub struct Formatter<'a> {
     source: Option<&'a str>,
     root: Option<&'a AstNode<'a>>,
     arena: Arena<AstNode<'a>>,
     options: ComrakOptions,
+    document: String,
+    diagnostics: RefCell<Vec<Diagnostic>>,
+    blocks: RefCell<Option<Blocks<'a>>>,
 }
 
 impl<'a> Default for Formatter<'a> {
@@ -24,22 +66,44 @@ impl<'a> Default for Formatter<'a> {
 
 impl<'a> Formatter<'a> {
     pub fn new() -> Self {
+        Self::with_config(&MarkdownConfig::default())
+    }
+
+    pub fn with_config(config: &MarkdownConfig) -> Self {
         let arena = Arena::new();
         let mut options = ComrakOptions::default();
 
-        options.extension.superscript = true;
-        options.extension.table = true;
+        options.extension.strikethrough = config.strikethrough;
+        options.extension.table = config.table;
+        options.extension.autolink = config.autolink;
+        options.extension.tasklist = config.tasklist;
+        options.extension.superscript = config.superscript;
+        options.extension.footnotes = config.footnotes;
+        options.extension.description_lists = config.description_lists;
 
-        options.parse.default_info_string = Some("text".into());
+        options.parse.smart = config.smart;
+        options.parse.default_info_string = config.default_info_string.clone();
+
+        options.render.hardbreaks = config.hardbreaks;
+        options.render.width = config.width;
 
         Self {
             source: Default::default(),
             root: None,
             arena,
             options,
+            document: Default::default(),
+            diagnostics: Default::default(),
+            blocks: Default::default(),
         }
     }
 
+    /// Set the document path recorded in the diagnostics.
+    pub fn with_document(mut self, document: impl Into<String>) -> Self {
+        self.document = document.into();
+        self
+    }
+
     pub fn parse(&'a mut self, markdown: &'a str) -> &Self {
         let root = parse_document(&self.arena, markdown, &self.options);
         self.source = Some(markdown);
@@ -47,9 +111,14 @@ impl<'a> Formatter<'a> {
         self
     }
 
-    pub fn format(&self, func: FormatFunction<'a>) -> &Self {
+    /// Root of the parsed document.
+    pub fn root(&self) -> Option<&'a AstNode<'a>> {
+        self.root
+    }
+
+    pub fn format(&self, name: &'static str, func: FormatFunction<'a>) -> &Self {
         if let Some(root) = self.root {
-            Self::iter_nodes(root, func);
+            self.iter_nodes(root, name, func);
             return self;
         }
         warn!("Can not format before parse.");
@@ -58,11 +127,12 @@ impl<'a> Formatter<'a> {
 
     pub fn format_with_args<Args>(
         &self,
+        name: &'static str,
         f: FormatFunctionWithArgs<'a, Args>,
         args: &'a Args,
     ) -> &Self {
         if let Some(root) = self.root {
-            Self::iter_nodes_with_args(root, f, args);
+            self.iter_nodes_with_args(root, name, f, args);
             return self;
         }
 
@@ -70,22 +140,315 @@ impl<'a> Formatter<'a> {
         self
     }
 
-    fn iter_nodes<'n>(node: &'n AstNode<'n>, f: FormatFunction<'n>) {
-        f(node).ok();
+    /// Run the transform on at most `concurrency` nodes at the same time.
+    pub async fn transform(
+        &self,
+        transform: &'a dyn Transform,
+        ctx: &'a TransformContext<'a>,
+        concurrency: usize,
+    ) -> &Self {
+        let Some(root) = self.root else {
+            warn!("Can not format before parse.");
+            return self;
+        };
+
+        let results = stream::iter(root.descendants().collect::<Vec<_>>())
+            .map(|node| transform.transform(node, ctx).map(move |res| (node, res)))
+            .buffered(concurrency)
+            .collect::<Vec<_>>()
+            .await;
+
+        for (node, res) in results {
+            if let Err(e) = res {
+                self.record(node, transform.name(), e);
+            }
+        }
+
+        self
+    }
+
+    /// Take the diagnostics collected so far.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.diagnostics.take()
+    }
+
+    fn iter_nodes(&self, node: &'a AstNode<'a>, name: &'static str, f: FormatFunction<'a>) {
+        if let Err(e) = f(node) {
+            self.record(node, name, e);
+        }
         for c in node.children() {
-            Self::iter_nodes(c, f);
+            self.iter_nodes(c, name, f);
         }
     }
 
-    fn iter_nodes_with_args<'n, Args>(
-        node: &'n AstNode<'n>,
-        f: FormatFunctionWithArgs<'n, Args>,
-        args: &'n Args,
+    fn iter_nodes_with_args<Args>(
+        &self,
+        node: &'a AstNode<'a>,
+        name: &'static str,
+        f: FormatFunctionWithArgs<'a, Args>,
+        args: &'a Args,
     ) {
-        f(node, args).ok();
+        if let Err(e) = f(node, args) {
+            self.record(node, name, e);
+        }
         for n in node.children() {
-            Self::iter_nodes_with_args(n, f, args);
+            self.iter_nodes_with_args(n, name, f, args);
+        }
+    }
+
+    fn record(&self, node: &'a AstNode<'a>, name: &str, error: Error) {
+        // Inline nodes have no position, use the nearest block instead
+        let line = node
+            .ancestors()
+            .map(|n| n.data.borrow().start_line)
+            .find(|line| *line != 0)
+            .unwrap_or_default();
+
+        debug!("`{}` failed at {}:{}: {}", name, self.document, line, error);
+
+        self.diagnostics.borrow_mut().push(Diagnostic {
+            transform: name.to_string(),
+            line,
+            document: self.document.clone(),
+            error,
+        });
+    }
+
+    /// Record the blocks, so [`Formatter::write_passthrough`] can tell
+    /// which of them are changed by the transforms.
+    pub fn snapshot(&self) -> &Self {
+        let (Some(root), Some(source)) = (self.root, self.source) else {
+            warn!("Can not snapshot before parse.");
+            return self;
+        };
+
+        let mut blocks = HashMap::new();
+        self.snapshot_block(root, 0..source.split_inclusive('\n').count(), &mut blocks);
+
+        *self.blocks.borrow_mut() = Some(blocks);
+
+        self
+    }
+
+    fn snapshot_block(&self, node: &'a AstNode<'a>, lines: Range<usize>, blocks: &mut Blocks<'a>) {
+        let kind = discriminant(&node.data.borrow().value);
+
+        let mut children = vec![];
+
+        if is_container(node) {
+            children = node.children().collect::<Vec<_>>();
+            // The footnote definitions are moved to the end of the document by the parser
+            children.sort_by_key(|child| child.data.borrow().start_line);
+
+            let starts = children
+                .iter()
+                .map(|child| (child.data.borrow().start_line as usize).saturating_sub(1))
+                .collect::<Vec<_>>();
+
+            for (i, child) in children.iter().enumerate() {
+                let start = starts[i].clamp(lines.start, lines.end);
+                let end = starts
+                    .get(i + 1)
+                    .map_or(lines.end, |next| *next)
+                    .clamp(start, lines.end);
+
+                self.snapshot_block(*child, start..end, blocks);
+            }
+        }
+
+        let rendered = match node.data.borrow().value {
+            NodeValue::Document => vec![],
+            _ => self.render(node),
+        };
+
+        blocks.insert(
+            key(node),
+            Block {
+                node,
+                lines,
+                children,
+                kind,
+                rendered,
+            },
+        );
+    }
+
+    /// Write the source as is except the blocks changed by the transforms, which are
+    /// rendered again. The unchanged children of the changed containers, such as the items
+    /// of a list, are kept. Falls back to [`Formatter::write_to`] without a snapshot.
+    pub fn write_passthrough(&self, file: &mut impl Write) {
+        let snapshot = self.blocks.borrow();
+
+        let (Some(root), Some(source), Some(blocks)) = (self.root, self.source, snapshot.as_ref())
+        else {
+            return self.write_to(file);
+        };
+
+        let Some(document) = blocks.get(&key(root)) else {
+            return self.write_to(file);
+        };
+
+        let lines = source.split_inclusive('\n').collect::<Vec<_>>();
+        let mut output = vec![];
+
+        let first = document
+            .children
+            .first()
+            .map_or(lines.len(), |child| blocks[&key(*child)].lines.start);
+        write_lines(&mut output, &lines, 0..first);
+
+        // Inserted by the transforms, written after the block before them
+        let mut inserted = HashMap::<*const AstNode<'a>, Vec<&'a AstNode<'a>>>::new();
+        let mut previous: *const AstNode<'a> = ptr::null();
+
+        for node in root.children() {
+            if blocks.contains_key(&key(node)) {
+                previous = node;
+            } else {
+                inserted.entry(previous).or_default().push(node);
+            }
+        }
+
+        let write_inserted = |output: &mut Vec<u8>, previous: *const AstNode<'a>| {
+            for node in inserted.get(&previous).into_iter().flatten() {
+                output.extend(self.render(node));
+                output.push(b'\n');
+            }
+        };
+
+        write_inserted(&mut output, ptr::null());
+
+        for child in &document.children {
+            let block = &blocks[&key(*child)];
+
+            if child.parent().is_some_and(|parent| ptr::eq(parent, root)) {
+                let mut rewritten = vec![];
+
+                if self
+                    .write_block(&mut rewritten, block, blocks, &lines)
+                    .is_none()
+                {
+                    rewritten.clear();
+                    self.rewrite(&mut rewritten, block, &lines, ("", ""));
+                }
+
+                output.extend(rewritten);
+            } else {
+                // Removed by the transforms
+                let (leading, trailing) =
+                    passthrough_lines(block.node, &lines[block.lines.clone()]);
+                write_lines(
+                    &mut output,
+                    &lines,
+                    block.lines.start..block.lines.start + leading,
+                );
+                write_lines(
+                    &mut output,
+                    &lines,
+                    block.lines.start + trailing..block.lines.end,
+                );
+            }
+
+            write_inserted(&mut output, key(*child));
         }
+
+        file.write_all(&output).ok();
+    }
+
+    /// Write the block, `None` if a changed block can not be put back into the lines of
+    /// its containers, then the caller rewrites the container instead.
+    fn write_block(
+        &self,
+        output: &mut Vec<u8>,
+        block: &Block<'a>,
+        blocks: &Blocks<'a>,
+        lines: &[&str],
+    ) -> Option<()> {
+        let node = block.node;
+
+        if self.render(node) == block.rendered {
+            write_lines(output, lines, block.lines.clone());
+            return Some(());
+        }
+
+        debug!("Block at line {} is changed.", block.lines.start + 1);
+
+        let children = node.children().collect::<Vec<_>>();
+        let same_children = children.len() == block.children.len()
+            && children
+                .iter()
+                .all(|child| block.children.iter().any(|c| ptr::eq(*c, *child)));
+
+        if is_container(node)
+            && discriminant(&node.data.borrow().value) == block.kind
+            && same_children
+        {
+            let first = block
+                .children
+                .first()
+                .map_or(block.lines.end, |child| blocks[&key(*child)].lines.start);
+            write_lines(output, lines, block.lines.start..first);
+
+            for child in &block.children {
+                self.write_block(output, &blocks[&key(*child)], blocks, lines)?;
+            }
+
+            return Some(());
+        }
+
+        let line = lines.get(block.lines.start)?;
+        let (first, rest) = line_prefix(node, line)?;
+
+        self.rewrite(output, block, lines, (&first, &rest));
+
+        Some(())
+    }
+
+    /// Render the block again, with the prefixes of its containers on the first
+    /// and the other lines. The reference definitions and blank lines are kept.
+    fn rewrite(
+        &self,
+        output: &mut Vec<u8>,
+        block: &Block<'a>,
+        lines: &[&str],
+        prefix: (&str, &str),
+    ) {
+        let (leading, trailing) = passthrough_lines(block.node, &lines[block.lines.clone()]);
+
+        write_lines(
+            output,
+            lines,
+            block.lines.start..block.lines.start + leading,
+        );
+
+        let rendered = self.render(block.node);
+
+        for (i, line) in String::from_utf8_lossy(&rendered)
+            .split_inclusive('\n')
+            .enumerate()
+        {
+            let prefix = if i == 0 { prefix.0 } else { prefix.1 };
+
+            if line.trim().is_empty() {
+                output.extend(prefix.trim_end().as_bytes());
+                output.push(b'\n');
+            } else {
+                output.extend(prefix.as_bytes());
+                output.extend(line.as_bytes());
+            }
+        }
+
+        if !rendered.ends_with(b"\n") {
+            output.push(b'\n');
+        }
+
+        write_lines(output, lines, block.lines.start + trailing..block.lines.end);
+    }
+
+    fn render(&self, node: &'a AstNode<'a>) -> Vec<u8> {
+        let mut output = vec![];
+        format_commonmark(node, &self.options, &mut output).ok();
+        output
     }
 
     pub fn write_to(&self, file: &mut impl Write) {
@@ -98,3 +461,170 @@ impl<'a> Formatter<'a> {
         warn!("Can not format before parse.");
     }
 }
+
+fn key<'a>(node: &'a AstNode<'a>) -> *const AstNode<'a> {
+    node
+}
+
+fn write_lines(output: &mut Vec<u8>, lines: &[&str], range: Range<usize>) {
+    for line in lines.get(range).unwrap_or_default() {
+        output.extend(line.as_bytes());
+    }
+}
+
+/// Containers whose unchanged children can be kept when they are changed.
+fn is_container<'a>(node: &'a AstNode<'a>) -> bool {
+    matches!(
+        node.data.borrow().value,
+        NodeValue::Document | NodeValue::List(_) | NodeValue::Item(_) | NodeValue::BlockQuote
+    )
+}
+
+/// Prefix of the containers before the block on its first line and on the other lines,
+/// the markers of the lists are replaced with spaces on the other lines.
+/// `None` if the prefix can not be told from the line, such as a lazy continuation line.
+fn line_prefix<'a>(node: &'a AstNode<'a>, line: &str) -> Option<(String, String)> {
+    let start_line = node.data.borrow().start_line;
+    let mut ancestors = node.ancestors().skip(1).collect::<Vec<_>>();
+    ancestors.reverse();
+
+    let (mut first, mut rest) = (String::new(), String::new());
+    let mut line = line;
+
+    for ancestor in ancestors {
+        match &ancestor.data.borrow().value {
+            NodeValue::Document | NodeValue::List(_) => (),
+            NodeValue::BlockQuote => {
+                let indent = line.len() - line.trim_start_matches(' ').len();
+                let quote = line
+                    .trim_start_matches(' ')
+                    .strip_prefix('>')
+                    .filter(|_| indent <= 3)?;
+                let width = indent + 1 + usize::from(quote.starts_with(' '));
+
+                first.push_str(&line[..width]);
+                rest.push_str(&line[..width]);
+                line = &line[width..];
+            }
+            NodeValue::Item(item) => {
+                let width = item.marker_offset + item.padding;
+                let marker = line.get(..width)?;
+
+                let continuation = ancestor.data.borrow().start_line != start_line;
+                if marker.contains('\t') || (continuation && !marker.trim().is_empty()) {
+                    return None;
+                }
+
+                first.push_str(marker);
+                rest.push_str(&" ".repeat(width));
+                line = &line[width..];
+            }
+            _ => return None,
+        }
+    }
+
+    Some((first, rest))
+}
+
+/// Number of the leading and the start of the trailing lines of the block which are not
+/// rendered from the node, such as the reference definitions and the blank lines after it.
+fn passthrough_lines<'a>(node: &'a AstNode<'a>, lines: &[&str]) -> (usize, usize) {
+    let content = |line: &str| {
+        line.trim_start_matches([' ', '\t', '>'])
+            .trim_end()
+            .to_string()
+    };
+    let is_blank = |line: &str| content(line).is_empty();
+    let is_definition = |line: &str| {
+        content(line)
+            .strip_prefix('[')
+            .and_then(|line| line.split_once(']'))
+            .is_some_and(|(label, rest)| !label.is_empty() && rest.starts_with(':'))
+    };
+
+    let data = node.data.borrow();
+    let value = &data.value;
+
+    // The reference definitions at the start of a paragraph are removed by the parser
+    let leading = match value {
+        NodeValue::Paragraph => lines.iter().take_while(|line| is_definition(line)).count(),
+        _ => 0,
+    };
+
+    let code = matches!(value, NodeValue::CodeBlock(_));
+    let mut trailing = lines.len()
+        - lines[leading..]
+            .iter()
+            .rev()
+            .take_while(|line| is_blank(line) || (!code && is_definition(line)))
+            .count();
+
+    // Lines without a blank line before them are continuations of these blocks
+    if matches!(
+        value,
+        NodeValue::Paragraph | NodeValue::HtmlBlock(_) | NodeValue::Table(_)
+    ) {
+        while lines.get(trailing).is_some_and(|line| !is_blank(line)) {
+            trailing += 1;
+        }
+    }
+
+    (leading, trailing.max(leading))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn replace_old<'a>(node: &'a AstNode<'a>) -> Result<()> {
+        if let NodeValue::Text(text) = &mut node.data.borrow_mut().value {
+            *text = String::from_utf8_lossy(text)
+                .replace("old", "new")
+                .into_bytes();
+        }
+
+        Ok(())
+    }
+
+    fn passthrough(markdown: &str) -> String {
+        let config = MarkdownConfig {
+            footnotes: true,
+            ..Default::default()
+        };
+
+        let mut formatter = Formatter::with_config(&config);
+        let formatter = formatter.parse(markdown);
+        formatter.snapshot().format("replace_old", replace_old);
+
+        let mut output = vec![];
+        formatter.write_passthrough(&mut output);
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn footnotes_and_definitions() {
+        let markdown = "# Title\n\nSome [link][ref] and a note[^1].\n\nold  paragraph\n\n[ref]: https://example.com\n\n[^1]: The note.\n\nLast  *paragraph*   kept.\n";
+
+        assert_eq!(
+            passthrough(markdown),
+            "# Title\n\nSome [link][ref] and a note[^1].\n\nnew  paragraph\n\n[ref]: https://example.com\n\n[^1]: The note.\n\nLast  *paragraph*   kept.\n"
+        );
+    }
+
+    #[test]
+    fn changed_in_containers() {
+        let markdown = "> quote old\n> second  line\n\n* item  one\n* item old\n  continued\n\n  next   paragraph\n";
+
+        assert_eq!(
+            passthrough(markdown),
+            "> quote new\n> second  line\n\n* item  one\n* item new\n  continued\n\n  next   paragraph\n"
+        );
+    }
+
+    #[test]
+    fn unchanged() {
+        let markdown = "Text   with *spaces*\n\n[a]: /a\n\n1) one\n2) two\n";
+
+        assert_eq!(passthrough(markdown), markdown);
+    }
+}
@@ -1,8 +1,7 @@
 use std::{
     borrow::Cow,
     cell::RefCell,
-    collections::HashMap,
-    io::{Cursor, Write},
+    collections::{BTreeMap, HashMap, HashSet},
     iter::zip,
     ops::Not,
     path::{Path, PathBuf},
@@ -10,9 +9,8 @@ use std::{
     time::Duration,
 };
 
-use base64::Engine;
-use comrak::nodes::{AstNode, NodeHeading, NodeHtmlBlock, NodeLink, NodeValue};
-use image::{DynamicImage, ImageOutputFormat};
+use comrak::nodes::{AstNode, NodeHeading, NodeValue};
+use futures_util::{stream, StreamExt};
 use log::{debug, error, info, warn};
 use regex::Regex;
 use serde_json::Value;
@@ -25,22 +23,48 @@ use tokio::{
 use yuque_rust::{DocsClient, Toc, Yuque};
 
 use crate::{
-    config::{CheckedGeneratorConfig, Namespace},
+    cache::{hash, DocCache, DocCacheEntry},
+    config::{CheckedGeneratorConfig, FrontmatterField, ImageConfig, MarkdownConfig, Namespace},
+    convert::{is_lake, is_sheet, lake_to_markdown, sheet_to_markdown},
     error::{Error, Result},
-    formatter::Formatter,
+    formatter::{Diagnostic, Formatter},
+    images::ImageCache,
+    limiter::RateLimiter,
+    report::{Problem, ProblemKind, Report},
     run_display_command_output,
     toc::{
-        generate::generate_doc_sidebar,
+        generate::{generate_doc_sidebar, is_namespace_dir},
         parse::{parse_toc_structure, Pinyin},
         Frontmatter, NavbarItem,
     },
-    CODEPEN_IFRAME, USER_AGENT,
+    transform::{
+        anchor_key, local_url, AnchorTransform, Transform, TransformContext, TransformRegistry,
+        ATTACHMENT_DIR, DEFAULT_TRANSFORMS, YUQUE_ANCHOR,
+    },
+    USER_AGENT,
 };
 
+const WORDS_PER_MINUTE: u64 = 300;
+
 pub struct Generator<'n> {
     inner: Arc<RwLock<GeneratorInner<'n>>>,
     pub article_path: RwLock<HashMap<String, HashMap<String, PathBuf>>>,
     pub schemas: Mutex<HashMap<String, Value>>,
+    pub cache: Mutex<DocCache>,
+    pub image_cache: Mutex<ImageCache>,
+    pub transforms: TransformRegistry,
+    pub limiter: RateLimiter,
+    /// Client for everything outside the Yuque api, such as the images
+    pub http_client: reqwest::Client,
+    pub report: Mutex<Report>,
+    /// Slugs of the Yuque anchors of each document
+    pub anchors: Mutex<HashMap<String, HashMap<String, String>>>,
+    pub yuque_domain: String,
+    pub yuque_token: String,
+    /// `base` of the site
+    pub base: String,
+    /// Hash of the global config and the version, part of the key of the cached documents
+    pub fingerprint: String,
 }
 
 pub struct GeneratorInner<'n> {
@@ -49,189 +73,406 @@ pub struct GeneratorInner<'n> {
     pub ns_id_path: HashMap<i32, PathBuf>,
     pub id_ns: HashMap<i32, Namespace<'n>>,
     pub build_command: Cow<'n, str>,
+    pub cache_dir: PathBuf,
+    pub concurrency: usize,
+    pub image: ImageConfig,
+    pub markdown: MarkdownConfig,
+    pub strict: bool,
 }
 
 impl<'n> Generator<'n> {
     pub fn from_config(config: CheckedGeneratorConfig<'n>) -> Self {
         let CheckedGeneratorConfig {
             host,
+            base,
             token,
             namespaces,
             build_command,
+            cache_dir,
+            concurrency,
+            rate_limit,
+            image,
+            sanitize,
+            embeds,
+            strict,
+            markdown,
         } = config;
 
+        let yuque_domain = url::Url::parse(&host)
+            .ok()
+            .and_then(|url| url.domain().map(String::from))
+            .unwrap_or_else(|| "yuque.com".into());
+
+        let yuque_token = token.to_string();
+
+        let fingerprint = hash(
+            serde_json::json!({
+                "version": env!("CARGO_PKG_VERSION"),
+                "domain": yuque_domain,
+                "base": base,
+                "image": image,
+                "sanitize": sanitize,
+                "embeds": embeds.providers().collect::<Vec<_>>(),
+                "markdown": markdown,
+            })
+            .to_string(),
+        );
+
         let client = Yuque::builder()
             .host(host.into())
             .token(token.into())
             .build()
             .unwrap();
 
-        let article_path: HashMap<String, HashMap<String, PathBuf>> = HashMap::new();
+        let cache_dir = PathBuf::from(cache_dir.as_ref());
+        let cache = DocCache::load(&cache_dir);
+        let image_cache = ImageCache::load(cache_dir.join("images"), image.cache_size << 20);
+
+        let mut article_path: HashMap<String, HashMap<String, PathBuf>> = HashMap::new();
+        let mut ns_id_path = HashMap::with_capacity(namespaces.len());
+        let mut id_ns = HashMap::with_capacity(namespaces.len());
+
+        let anchors = cache
+            .namespaces()
+            .flat_map(|ns| ns.docs.values())
+            .map(|doc| {
+                (
+                    anchor_key(&doc.path.display().to_string()),
+                    doc.anchors.clone(),
+                )
+            })
+            .collect();
+
+        for ns in namespaces.iter() {
+            if let Some(cached) = cache.namespace(&ns.target) {
+                debug!("Restore `{}` from cache.", ns.target);
+                article_path.insert(cached.namespace.clone(), cached.articles.clone());
+                ns_id_path.insert(cached.book_id, cached.path.clone());
+                id_ns.insert(cached.book_id, ns.clone());
+            }
+        }
 
         Self {
             inner: Arc::new(RwLock::new(GeneratorInner {
                 client,
-                ns_id_path: HashMap::with_capacity(namespaces.len()),
-                id_ns: HashMap::with_capacity(namespaces.len()),
+                ns_id_path,
+                id_ns,
                 namespaces,
                 build_command,
+                cache_dir,
+                concurrency,
+                image: image.clone(),
+                markdown,
+                strict,
             })),
             article_path: RwLock::new(article_path),
             schemas: Mutex::new(HashMap::new()),
+            cache: Mutex::new(cache),
+            image_cache: Mutex::new(image_cache),
+            transforms: TransformRegistry::new(image, sanitize, embeds),
+            limiter: RateLimiter::new(rate_limit),
+            http_client: reqwest::Client::builder()
+                .user_agent(USER_AGENT)
+                .connect_timeout(Duration::from_secs(10))
+                .timeout(Duration::from_secs(60))
+                .pool_idle_timeout(Duration::from_secs(90))
+                .build()
+                .unwrap(),
+            report: Mutex::new(Report::default()),
+            anchors: Mutex::new(anchors),
+            yuque_domain,
+            yuque_token,
+            base: base.to_string(),
+            fingerprint,
         }
     }
 
     pub async fn generate_one(&self, ns: &Namespace<'n>) -> Result<(NavbarItem, (i32, PathBuf))> {
+        let resolved = self.resolve_namespace(ns).await?;
+        let resolved = self.prefetch_namespace(ns, resolved).await;
+
+        self.write_namespace(ns, resolved).await
+    }
+
+    /// Fetch the structure of the namespace and record the path of each document,
+    /// all namespaces are resolved before writing so the links across them can be rewritten.
+    async fn resolve_namespace(&self, ns: &Namespace<'n>) -> Result<ResolvedNamespace> {
         let name = &ns.target;
-        let text = &ns.text;
-        let toc = ns.toc;
 
         let repos = self.inner.read().await.client.repos();
         let docs = self.inner.read().await.client.docs();
 
-        let navbar_item: NavbarItem;
-        let p: (i32, PathBuf);
+        self.limiter.wait().await;
+        let response = match repos.get(name, None).await {
+            Ok(response) => response.data,
+            Err(e) => {
+                warn!("Can not get the repo info due to {}.", e);
+                warn!("retry after 3s.");
+                sleep(Duration::from_secs(3)).await;
+                self.limiter.wait().await;
+                repos.get(name, None).await?.data
+            }
+        };
 
-        let mut article_path = self.article_path.write().await;
+        self.limiter.wait().await;
+        let list = docs.list_with_repo(name).await?.data;
 
-        if toc {
-            let response = match repos.get(name, None).await {
-                Ok(response) => response.data,
-                Err(e) => {
-                    warn!("Can not get the repo info due to {}.", e);
-                    warn!("retry after 3s.");
-                    sleep(Duration::from_secs(3)).await;
-                    repos.get(name, None).await?.data
-                }
-            };
+        let updated = list
+            .iter()
+            .map(|item| (item.id as u32, item.updated_at.to_string()))
+            .collect::<HashMap<_, _>>();
 
-            let description = response.description.unwrap_or_default();
-            let mut toc = response.toc.unwrap();
+        let ns_path = response.name.to_lowercase();
+        let mut articles = HashMap::new();
+        let mut entries = vec![];
 
-            article_path.insert(response.namespace.to_string(), HashMap::default());
-            let ns_inner_path = article_path.get_mut(response.namespace.as_ref()).unwrap();
+        if ns.toc {
+            let mut toc = response.toc.unwrap_or_default();
 
-            toc.remove(0);
-
-            let ns_path = &response.name.to_lowercase();
+            if !toc.is_empty() {
+                toc.remove(0);
+            }
 
-            let paths = parse_toc_structure(ns_path, &toc);
+            let paths = parse_toc_structure(&ns_path, &toc);
 
-            for (path, item) in zip(&paths, &toc) {
-                if let Toc::Doc(doc) = &item {
-                    ns_inner_path.insert(doc.url.to_string(), path.clone());
+            for (order, (path, item)) in zip(paths, toc).enumerate() {
+                match item {
+                    Toc::Doc(doc) => {
+                        articles.insert(doc.url.to_string(), path.clone());
+                        let updated_at = updated.get(&doc.id).cloned();
+                        entries.push((order, path, Entry::Doc(doc.id, updated_at, None)));
+                    }
+                    Toc::Title(title) => {
+                        entries.push((order, path, Entry::Title(title.title.to_string())));
+                    }
+                    _ => (),
                 }
             }
+        } else {
+            for (order, item) in list.iter().enumerate() {
+                let file_name = item.title.to_pinyin_or_lowercase();
 
-            drop(article_path);
+                articles.insert(
+                    item.slug.to_string(),
+                    PathBuf::from(format!("./docs/{}/{}.md", ns_path, file_name)),
+                );
 
-            for (i, (path, item)) in zip(paths, toc).enumerate() {
-                match &self
-                    .write_markdown_with_toc(&docs, path, name, item, i)
-                    .await
-                {
-                    Ok(_) => (),
-                    Err(e) => {
-                        warn!("Can not write the file due to {}.", e);
-                        warn!("Skip.");
-                    }
-                }
+                let path = PathBuf::from(format!("docs/{}/{}.md", ns_path, file_name));
+                let updated_at = Some(item.updated_at.to_string());
+                entries.push((order, path, Entry::Doc(item.id as u32, updated_at, None)));
             }
+        }
 
-            navbar_item = NavbarItem {
-                text: text.to_string(),
-                link: format!("/{}/", ns_path),
-                items: None,
-            };
+        self.update_namespace_cache(
+            name,
+            response.id,
+            &response.namespace,
+            PathBuf::from(format!("docs/{}/", ns_path)),
+            articles.clone(),
+        );
 
-            let mut index_file = File::create(format!("docs/{}/index.md", &ns_path)).await?;
+        self.retain_cache(name, updated.keys().copied().collect());
 
-            index_file
-                .write_all(format!("# {}\n", response.name).as_bytes())
-                .await?;
+        self.article_path
+            .write()
+            .await
+            .insert(response.namespace.to_string(), articles);
 
-            index_file.write_all(description.as_bytes()).await?;
+        Ok(ResolvedNamespace {
+            book_id: response.id,
+            title: response.name.to_string(),
+            path: ns_path,
+            description: response.description.unwrap_or_default().to_string(),
+            entries,
+        })
+    }
 
-            p = (response.id, PathBuf::from(format!("docs/{}/", ns_path)));
-        } else {
-            let response = match repos.get(name, None).await {
-                Ok(response) => response.data,
-                Err(e) => {
-                    warn!("Can not get the repo info due to {}.", e);
-                    warn!("retry after 3s.");
-                    sleep(Duration::from_secs(3)).await;
-                    repos.get(name, None).await?.data
-                }
-            };
+    /// Fetch the documents which can not be taken from the cache and record the anchors of
+    /// their headings, so the links to them are the same whatever order they are written in.
+    async fn prefetch_namespace(
+        &self,
+        ns: &Namespace<'n>,
+        resolved: ResolvedNamespace,
+    ) -> ResolvedNamespace {
+        let docs = self.inner.read().await.client.docs();
+        let concurrency = self.inner.read().await.concurrency;
+        let markdown = self.inner.read().await.markdown.clone();
+
+        let fingerprint = self.namespace_fingerprint(ns);
+        let anchor = match &ns.transforms {
+            Some(configs) => configs
+                .iter()
+                .any(|config| config.name == "anchor" && config.enabled),
+            None => DEFAULT_TRANSFORMS.contains(&"anchor"),
+        }
+        .then(AnchorTransform::new);
 
-            article_path.insert(response.namespace.to_string(), HashMap::default());
-            let ns_inner_path = article_path.get_mut(response.namespace.as_ref()).unwrap();
+        let (docs, fingerprint, anchor, markdown) = (&docs, &fingerprint, &anchor, &markdown);
 
-            let book_id = response.id;
-            let ns_name = response.name.to_string();
-            let ns_path = ns_name.to_lowercase();
-            let description = response.description.unwrap_or_default();
-            let response = docs.list_with_repo(name).await?.data;
+        let entries = stream::iter(resolved.entries)
+            .map(|(order, path, entry)| async move {
+                let (id, updated_at) = match entry {
+                    Entry::Doc(id, updated_at, None) => (id, updated_at),
+                    entry => return (order, path, entry),
+                };
 
-            for item in response.iter() {
-                ns_inner_path.insert(
-                    item.slug.to_string(),
-                    PathBuf::from(format!(
-                        "./docs/{}/{}.md",
-                        ns_path,
-                        item.title.to_pinyin_or_lowercase()
-                    )),
-                );
-            }
+                let cached = self
+                    .cache
+                    .lock()
+                    .unwrap()
+                    .get(&ns.target, id)
+                    .filter(|entry| {
+                        Some(&entry.updated_at) == updated_at.as_ref()
+                            && &entry.fingerprint == fingerprint
+                    })
+                    .map(|entry| entry.anchors.clone());
+
+                let (anchors, fetched) = match cached {
+                    Some(anchors) => (anchors, None),
+                    None => match self.fetch_doc(docs, &ns.target, id).await {
+                        Ok(doc) => {
+                            let anchors = {
+                                let mut formatter = Formatter::with_config(markdown);
+                                let formatter = formatter.parse(&doc.body);
+
+                                anchor
+                                    .as_ref()
+                                    .zip(formatter.root())
+                                    .map(|(anchor, root)| anchor.rewrite(root))
+                                    .unwrap_or_default()
+                            };
+
+                            (anchors, Some(Box::new(doc)))
+                        }
+                        Err(e) => {
+                            // Fetched again when written, where the error is reported
+                            warn!("Can not fetch the document {} due to {}.", id, e);
+                            (HashMap::new(), None)
+                        }
+                    },
+                };
+
+                self.anchors
+                    .lock()
+                    .unwrap()
+                    .insert(anchor_key(&path.display().to_string()), anchors);
+
+                (order, path, Entry::Doc(id, updated_at, fetched))
+            })
+            .buffered(concurrency)
+            .collect::<Vec<_>>()
+            .await;
+
+        ResolvedNamespace {
+            entries,
+            ..resolved
+        }
+    }
 
-            for (i, item) in response.into_iter().enumerate() {
-                let path = PathBuf::from(format!(
-                    "docs/{}/{}.md",
-                    ns_path,
-                    item.title.to_pinyin_or_lowercase()
-                ));
+    async fn write_namespace(
+        &self,
+        ns: &Namespace<'n>,
+        resolved: ResolvedNamespace,
+    ) -> Result<(NavbarItem, (i32, PathBuf))> {
+        let name = &ns.target;
 
-                match self
-                    .write_markdown(&docs, path, name, item.id as u32, i)
+        let docs = self.inner.read().await.client.docs();
+        let concurrency = self.inner.read().await.concurrency;
+
+        let ResolvedNamespace {
+            book_id,
+            title,
+            path: ns_path,
+            description,
+            entries,
+        } = resolved;
+
+        let docs = &docs;
+        let options = &NamespaceOptions {
+            transforms: self.transforms.build(ns.transforms.as_deref())?,
+            fields: &ns.frontmatter,
+            fingerprint: self.namespace_fingerprint(ns),
+        };
+
+        stream::iter(entries)
+            .for_each_concurrent(concurrency, |(order, path, entry)| async move {
+                let document = path.display().to_string();
+
+                if let Err(e) = self
+                    .write_entry(docs, options, path, name, entry, order)
                     .await
                 {
-                    Ok(_) => (),
-                    Err(e) => {
-                        warn!("Can not write the file due to {}.", e);
-                        warn!("Skip.");
-                    }
+                    warn!("Can not write the file due to {}.", e);
+                    warn!("Skip.");
+                    self.report
+                        .lock()
+                        .unwrap()
+                        .push(ProblemKind::Document, document, e);
                 }
-            }
-
-            navbar_item = NavbarItem {
-                text: text.to_string(),
-                link: format!("/{}/", ns_path),
-                items: None,
-            };
+            })
+            .await;
 
-            let mut index_file = File::create(format!("docs/{}/index.md", &ns_path)).await?;
+        let navbar_item = NavbarItem {
+            text: ns.text.to_string(),
+            link: format!("/{}/", ns_path),
+            items: None,
+        };
 
-            index_file
-                .write_all(format!("# {}\n", ns_name).as_bytes())
-                .await?;
+        let index_path = PathBuf::from(format!("docs/{}/index.md", ns_path));
+        let index = format!("# {}\n{}", title, description);
 
-            index_file.write_all(description.as_bytes()).await?;
+        write_file(&index_path, index.as_bytes()).await?;
 
-            p = (book_id, PathBuf::from(format!("docs/{}/", ns_path)));
-        }
-
-        Ok((navbar_item, p))
+        Ok((
+            navbar_item,
+            (book_id, PathBuf::from(format!("docs/{}/", ns_path))),
+        ))
     }
 
     pub async fn generate_all(&self) -> Result<()> {
+        self.report.lock().unwrap().clear();
+
         let mut default_navbar = vec![];
         let mut ns_id_paths = vec![];
         let mut id_ns = vec![];
         let mut custom_navbar_map = HashMap::new();
         let mut custom_navbar_list = vec![];
 
-        for namespace in self.inner.read().await.namespaces.iter() {
-            let (n, p) = self.generate_one(namespace).await?;
+        let inner = self.inner.read().await;
+
+        let resolved = stream::iter(inner.namespaces.iter())
+            .map(|namespace| async move { (namespace, self.resolve_namespace(namespace).await) })
+            .buffered(inner.concurrency)
+            .collect::<Vec<_>>()
+            .await;
+
+        let mut ready = vec![];
+        for (namespace, result) in resolved {
+            ready.push((namespace, result?));
+        }
+
+        // The anchors of all documents are needed before the links to them are rewritten
+        let ready = stream::iter(ready)
+            .map(|(namespace, resolved)| async move {
+                (
+                    namespace,
+                    self.prefetch_namespace(namespace, resolved).await,
+                )
+            })
+            .buffered(inner.concurrency)
+            .collect::<Vec<_>>()
+            .await;
+
+        let generated = stream::iter(ready)
+            .map(|(namespace, resolved)| async move {
+                (namespace, self.write_namespace(namespace, resolved).await)
+            })
+            .buffered(inner.concurrency)
+            .collect::<Vec<_>>()
+            .await;
+
+        for (namespace, result) in generated {
+            let (n, p) = result?;
             id_ns.push((p.0, namespace.clone()));
             ns_id_paths.push(p);
 
@@ -250,6 +491,8 @@ impl<'n> Generator<'n> {
             }
         }
 
+        drop(inner);
+
         generate_doc_sidebar("./docs")?;
 
         self.inner.write().await.ns_id_path.extend(ns_id_paths);
@@ -290,7 +533,12 @@ impl<'n> Generator<'n> {
 
         info!("Generate markdown schema.");
 
-        Ok(())
+        self.write_edit_link().await?;
+
+        self.cache.lock().unwrap().save()?;
+        self.image_cache.lock().unwrap().save()?;
+
+        self.finish_report().await
     }
 
     pub async fn build(&self) -> Result<()> {
@@ -311,6 +559,7 @@ impl<'n> Generator<'n> {
             info!("Build Finished.");
         } else {
             error!("Build Failed.");
+            return Err(Error::Build(cmd.to_string()));
         }
 
         Ok(())
@@ -325,7 +574,81 @@ impl<'n> Generator<'n> {
         Ok(())
     }
 
+    /// Remove the generated knowledge base directories, the generated configs and the build output.
+    pub async fn clean_all(&self) -> Result<()> {
+        let inner = self.inner.read().await;
+
+        // Only the directories known to be generated, the others under `docs` may be hand-written
+        let mut dirs = inner.ns_id_path.values().cloned().collect::<HashSet<_>>();
+        dirs.extend(
+            self.cache
+                .lock()
+                .unwrap()
+                .namespaces()
+                .map(|ns| ns.path.clone()),
+        );
+
+        for dir in dirs {
+            let is_generated = dir.parent() == Some(Path::new("docs"))
+                && dir
+                    .file_name()
+                    .is_some_and(|name| is_namespace_dir(&name.to_string_lossy()));
+
+            if is_generated && fs::try_exists(&dir).await? {
+                warn!("removing dir: {}", dir.display());
+                remove_dir_all(&dir).await?;
+            }
+        }
+
+        for file in [
+            "./nav.json",
+            "./sidebar.json",
+            "./schema.json",
+            "./report.json",
+            "./edit_link.mjs",
+        ] {
+            if fs::try_exists(file).await? {
+                warn!("removing file: {}", file);
+                fs::remove_file(file).await?;
+            }
+        }
+
+        if fs::try_exists("./docs/.vitepress/dist").await? {
+            warn!("removing dir: ./docs/.vitepress/dist");
+            remove_dir_all("./docs/.vitepress/dist").await?;
+        }
+
+        for assets_dir in public_dirs(
+            &inner.namespaces,
+            "convert_image",
+            "assets",
+            &inner.image.assets,
+        ) {
+            if fs::try_exists(&assets_dir).await? {
+                warn!("removing dir: {}", assets_dir.display());
+                remove_dir_all(&assets_dir).await?;
+            }
+        }
+
+        for attachments_dir in public_dirs(&inner.namespaces, "attachment", "dir", ATTACHMENT_DIR) {
+            if fs::try_exists(&attachments_dir).await? {
+                warn!("removing dir: {}", attachments_dir.display());
+                remove_dir_all(&attachments_dir).await?;
+            }
+        }
+
+        let cache_dir = inner.cache_dir.clone();
+        if fs::try_exists(&cache_dir).await? {
+            warn!("removing dir: {}", cache_dir.display());
+            remove_dir_all(&cache_dir).await?;
+        }
+
+        Ok(())
+    }
+
     pub async fn regenerate(&self, book_id: i32) -> Result<()> {
+        self.report.lock().unwrap().clear();
+
         self.clean(book_id).await?;
 
         if let Some(ns) = self.inner.read().await.id_ns.get(&book_id) {
@@ -336,23 +659,68 @@ impl<'n> Generator<'n> {
         generate_doc_sidebar("./docs")?;
 
         self.write_schema().await?;
+        self.write_edit_link().await?;
+
+        self.cache.lock().unwrap().save()?;
+        self.image_cache.lock().unwrap().save()?;
+
+        self.finish_report().await
+    }
+
+    /// Write the report of this build, fail in strict mode if there is any problem.
+    async fn finish_report(&self) -> Result<()> {
+        let strict = self.inner.read().await.strict;
+        let report = self.report.lock().unwrap();
+
+        report.write_to("./report.json")?;
+        report.log_summary();
+
+        if strict && !report.is_empty() {
+            return Err(Error::Strict(report.len()));
+        }
 
         Ok(())
     }
 
-    async fn write_markdown_with_toc(
+    fn update_namespace_cache(
+        &self,
+        ns: &str,
+        book_id: i32,
+        namespace: &str,
+        path: PathBuf,
+        articles: HashMap<String, PathBuf>,
+    ) {
+        let mut cache = self.cache.lock().unwrap();
+        let cached = cache.namespace_mut(ns);
+
+        cached.book_id = book_id;
+        cached.namespace = namespace.to_string();
+        cached.path = path;
+        cached.articles = articles;
+    }
+
+    fn retain_cache(&self, ns: &str, ids: HashSet<u32>) {
+        self.cache.lock().unwrap().retain(ns, &ids);
+    }
+
+    async fn write_entry(
         &self,
         client: &DocsClient,
+        options: &NamespaceOptions<'_>,
         path: PathBuf,
         ns: &str,
-        doc: Toc<'_>,
+        entry: Entry,
         order: usize,
     ) -> Result<()> {
-        match doc {
-            Toc::Doc(doc) => {
-                self.write_markdown(client, path, ns, doc.id, order).await?;
+        match entry {
+            Entry::Doc(id, updated_at, fetched) => {
+                let problems = self
+                    .write_markdown(client, options, path, ns, id, order, updated_at, fetched)
+                    .await?;
+
+                self.report.lock().unwrap().extend(problems);
             }
-            Toc::Title(title) => {
+            Entry::Title(title) => {
                 if path.exists().not() {
                     fs::create_dir_all(&path).await?;
                 }
@@ -362,85 +730,328 @@ impl<'n> Generator<'n> {
                 let mut file = std::fs::File::create(file_path)?;
 
                 Frontmatter::builder()
-                    .sidebar(title.title.clone())
+                    .sidebar(title.clone().into())
                     .order(order as u32)
                     .have_content(false)
-                    .title_template(Some(title.title.clone()))
+                    .title_template(Some(title.into()))
                     .build()?
                     .write_to(&mut file);
 
                 debug!("Write frontmatter to: index.md",);
             }
-            _ => (),
         }
 
         Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn write_markdown(
         &self,
         client: &DocsClient,
+        options: &NamespaceOptions<'_>,
         path: PathBuf,
         ns: &str,
         id: u32,
         order: usize,
-    ) -> Result<()> {
-        let doc = client
-            .get_with_repo_ns(ns, id, Some(&[("raw", "1")]))
-            .await?
-            .data;
+        updated_at: Option<String>,
+        fetched: Option<Box<FetchedDoc>>,
+    ) -> Result<Vec<Problem>> {
+        let schema_key = schema_key(&path);
+
+        if let Some(updated_at) = &updated_at {
+            let cached = self
+                .cache
+                .lock()
+                .unwrap()
+                .get(ns, id)
+                .filter(|entry| {
+                    &entry.updated_at == updated_at && is_fresh(entry, &path, order, options)
+                })
+                .cloned();
+
+            if let Some(entry) = cached {
+                if self.links_unchanged(ns, &entry).await {
+                    debug!("Document not changed, use cache: {}", path.display());
+                    return self.write_cached(&path, &schema_key, entry).await;
+                }
+            }
+        }
 
-        info!("Find doc: {}", doc.title);
+        let doc = match fetched {
+            Some(doc) => *doc,
+            None => self.fetch_doc(client, ns, id).await?,
+        };
 
         debug!("doc path: {}", path.display());
 
-        let parent_path = path.parent().unwrap();
-
-        if parent_path.exists().not() {
-            fs::create_dir_all(parent_path).await?;
+        let body_hash = doc.hash.clone();
+        let updated_at = updated_at.unwrap_or_default();
+
+        let cached = self
+            .cache
+            .lock()
+            .unwrap()
+            .get(ns, id)
+            .filter(|entry| entry.hash == body_hash && is_fresh(entry, &path, order, options))
+            .cloned();
+
+        if let Some(entry) = cached {
+            if self.links_unchanged(ns, &entry).await {
+                debug!(
+                    "Document content not changed, use cache: {}",
+                    path.display()
+                );
+                let entry = DocCacheEntry {
+                    updated_at,
+                    ..entry
+                };
+                self.cache.lock().unwrap().insert(ns, id, entry.clone());
+                return self.write_cached(&path, &schema_key, entry).await;
+            }
         }
 
-        let mut file = std::fs::File::create(&path)?;
+        let mut output = vec![];
+
+        let field = |field| options.fields.contains(&field);
+        let word_count = doc.word_count;
 
         Frontmatter::builder()
-            .sidebar(doc.title.clone())
+            .sidebar(doc.title.as_str().into())
             .order(order as u32)
-            .title_template(Some(doc.title.clone()))
+            .title_template(Some(doc.title.as_str().into()))
+            .author(
+                doc.author
+                    .as_deref()
+                    .filter(|_| field(FrontmatterField::Author))
+                    .map(Into::into),
+            )
+            .created_at(field(FrontmatterField::CreatedAt).then(|| doc.created_at.as_str().into()))
+            .updated_at(field(FrontmatterField::UpdatedAt).then(|| doc.updated_at.as_str().into()))
+            .word_count(field(FrontmatterField::WordCount).then_some(word_count))
+            .reading_time(field(FrontmatterField::ReadingTime).then(|| reading_time(word_count)))
+            .cover(
+                doc.cover
+                    .as_deref()
+                    .filter(|_| field(FrontmatterField::Cover))
+                    .map(Into::into),
+            )
+            .source(field(FrontmatterField::Source).then(|| doc.source.as_str().into()))
             .build()?
-            .write_to(&mut file);
+            .write_to(&mut output);
 
-        debug!(
-            "Write frontmatter to: {}",
-            path.file_name().unwrap().to_string_lossy()
-        );
+        output.extend_from_slice(format!("# {}\n", doc.title).as_bytes());
 
-        file.write_all(format!("# {}\n", doc.title).as_bytes())?;
+        let document = path.display().to_string();
+        let article_path = self.article_path.read().await;
+        let links = Mutex::new(HashMap::new());
 
-        let client = reqwest::blocking::Client::builder()
-            .user_agent(USER_AGENT)
-            .build()
-            .unwrap();
+        let ctx = self.context(ns, &document, &article_path, &links);
+
+        let content = filter_schema(&doc.body, &schema_key, &self.schemas);
+
+        let concurrency = self.inner.read().await.concurrency;
+        let markdown = self.inner.read().await.markdown.clone();
+
+        let problems = {
+            let mut formatter = Formatter::with_config(&markdown).with_document(&document);
+
+            let formatter = formatter.parse(&content);
+
+            if markdown.passthrough {
+                formatter.snapshot();
+            }
+
+            for transform in &options.transforms {
+                formatter
+                    .transform(transform.as_ref(), &ctx, concurrency)
+                    .await;
+            }
+
+            if markdown.passthrough {
+                formatter.write_passthrough(&mut output);
+            } else {
+                formatter.write_to(&mut output);
+            }
+
+            formatter
+                .diagnostics()
+                .into_iter()
+                .map(problem)
+                .collect::<Vec<_>>()
+        };
+
+        let entry = DocCacheEntry {
+            updated_at,
+            hash: body_hash,
+            path: path.clone(),
+            order,
+            schema: self.schemas.lock().unwrap().get(&schema_key).cloned(),
+            output: String::from_utf8_lossy(&output).to_string(),
+            problems: problems.clone(),
+            anchors: self
+                .anchors
+                .lock()
+                .unwrap()
+                .get(&anchor_key(&document))
+                .cloned()
+                .unwrap_or_default(),
+            source: doc.source.clone(),
+            fingerprint: options.fingerprint.clone(),
+            links: std::mem::take(&mut *links.lock().unwrap()),
+        };
 
+        self.cache.lock().unwrap().insert(ns, id, entry);
+
+        write_file(&path, &output).await?;
+
+        Ok(problems)
+    }
+
+    fn context<'c>(
+        &'c self,
+        ns: &'c str,
+        document: &'c str,
+        article_path: &'c HashMap<String, HashMap<String, PathBuf>>,
+        links: &'c Mutex<HashMap<String, String>>,
+    ) -> TransformContext<'c> {
+        TransformContext {
+            namespace: ns,
+            document,
+            article_path,
+            yuque_domain: &self.yuque_domain,
+            token: &self.yuque_token,
+            base: &self.base,
+            client: &self.http_client,
+            image_cache: &self.image_cache,
+            anchors: &self.anchors,
+            links,
+        }
+    }
+
+    /// Fetch the document and convert its body into markdown.
+    async fn fetch_doc(&self, client: &DocsClient, ns: &str, id: u32) -> Result<FetchedDoc> {
+        self.limiter.wait().await;
+
+        let doc = client
+            .get_with_repo_ns(ns, id, Some(&[("raw", "1")]))
+            .await?
+            .data;
+
+        info!("Find doc: {}", doc.title);
+
+        // The sheets which can not be converted are reported as problems
+        let body = if is_sheet(&doc.format.to_string()) {
+            info!("Convert sheet document: {}", doc.title);
+            sheet_to_markdown(&doc.body)?
+        } else if is_lake(&doc.body) {
+            info!("Convert lake document: {}", doc.title);
+            lake_to_markdown(&doc.body)
+        } else {
+            doc.body.to_string()
+        };
+
+        Ok(FetchedDoc {
+            title: doc.title.to_string(),
+            source: format!("https://{}/{}/{}", self.yuque_domain, ns, doc.slug),
+            hash: hash(doc.body.as_bytes()),
+            body,
+            author: doc.creator.as_ref().map(|creator| creator.name.to_string()),
+            created_at: doc.created_at.to_string(),
+            updated_at: doc.updated_at.to_string(),
+            word_count: doc.word_count as u64,
+            cover: doc
+                .cover
+                .as_ref()
+                .map(|cover| cover.to_string())
+                .filter(|cover| !cover.is_empty()),
+        })
+    }
+
+    /// Hash of the global and the namespace config, part of the key of the cached documents.
+    fn namespace_fingerprint(&self, ns: &Namespace) -> String {
+        hash(format!(
+            "{}{}",
+            self.fingerprint,
+            serde_json::json!({
+                "transforms": ns.transforms,
+                "frontmatter": ns.frontmatter,
+            })
+        ))
+    }
+
+    /// Whether the Yuque links in the cached output still point to the same pages,
+    /// the linked documents may be moved, renamed or created since then.
+    async fn links_unchanged(&self, ns: &str, entry: &DocCacheEntry) -> bool {
+        let document = entry.path.display().to_string();
         let article_path = self.article_path.read().await;
-        let default_map = HashMap::default();
+        let links = Mutex::new(HashMap::new());
+        let ctx = self.context(ns, &document, &article_path, &links);
 
-        let articles = article_path.get(ns).unwrap_or(&default_map);
+        entry.links.iter().all(|(url, local)| {
+            let resolved = url::Url::parse(url)
+                .ok()
+                .and_then(|url| local_url(&url, &ctx).ok())
+                .unwrap_or_default();
+
+            &resolved == local
+        })
+    }
+
+    async fn write_cached(
+        &self,
+        path: &Path,
+        schema_key: &str,
+        entry: DocCacheEntry,
+    ) -> Result<Vec<Problem>> {
+        if let Some(schema) = entry.schema {
+            self.schemas
+                .lock()
+                .unwrap()
+                .insert(schema_key.to_string(), schema);
+        }
 
-        let mut formatter = Formatter::new();
+        write_file(path, entry.output.as_bytes()).await?;
 
-        let mut ap = path.components();
-        ap.next();
-        ap.next();
+        Ok(entry.problems)
+    }
 
-        let content = filter_schema(&doc.body, ap.collect::<PathBuf>().as_path(), &self.schemas);
+    /// Write `editLink` of the theme config as a module, whose `pattern` finds the Yuque
+    /// edit url of the page by its path relative to `docs`. The theme imports it in
+    /// `docs/.vitepress/config` by `import editLink from "../../edit_link.mjs"`, the function
+    /// `pattern` requires VitePress 1.0 or later.
+    async fn write_edit_link(&self) -> Result<()> {
+        // Only the namespaces in the config, the cache may have the removed ones
+        let links = self
+            .article_path
+            .read()
+            .await
+            .iter()
+            .flat_map(|(ns, articles)| {
+                articles.iter().map(move |(slug, path)| {
+                    let path = path.strip_prefix("./").unwrap_or(path);
+                    let path = path.strip_prefix("docs").unwrap_or(path);
+
+                    (
+                        path.display().to_string(),
+                        format!("https://{}/{}/{}/edit", self.yuque_domain, ns, slug),
+                    )
+                })
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        let module = format!(
+            "const links = {};\n\nexport default {{\n  text: {},\n  pattern: ({{ relativePath }}) => links[relativePath] ?? {},\n}};\n",
+            serde_json::to_string_pretty(&links)?,
+            serde_json::json!("在语雀上编辑此页"),
+            serde_json::json!(format!("https://{}", self.yuque_domain)),
+        );
 
-        formatter
-            .parse(&content)
-            .format_with_args(convert_image_to_base64, &client)
-            .format_with_args(convert_link, articles)
-            .write_to(&mut file);
+        File::create("./edit_link.mjs")
+            .await?
+            .write_all(module.as_bytes())
+            .await?;
 
-        debug!("Write File to: {}", path.display());
+        info!("Generate edit link config.");
 
         Ok(())
     }
@@ -491,114 +1102,112 @@ impl<'n> Generator<'n> {
     }
 }
 
-impl<'a> From<CheckedGeneratorConfig<'a>> for Generator<'a> {
-    fn from(value: CheckedGeneratorConfig<'a>) -> Self {
-        Self::from_config(value)
-    }
-}
-
-fn image_to_base64(img: &DynamicImage) -> String {
-    let mut image_data: Vec<u8> = Vec::new();
-    img.write_to(&mut Cursor::new(&mut image_data), ImageOutputFormat::Png)
-        .unwrap();
-
-    info!("Convert image to base64 string.");
-
-    let res_base64 = base64::prelude::BASE64_STANDARD.encode(image_data);
-    format!("data:image/png;base64,{}", res_base64)
-}
-
-fn convert_image_to_base64<'a>(
-    node: &'a AstNode<'a>,
-    client: &reqwest::blocking::Client,
-) -> Result<()> {
-    let mut svg = vec![];
-
-    if let NodeValue::Image(i) = &mut node.data.borrow_mut().value {
-        let url = String::from_utf8_lossy(&i.url).to_string();
-        let url = url::Url::parse(&url)?;
-
-        info!("Find image url: {}", url);
-
-        let response = client.get(url).send()?;
-
-        let bytes = response.bytes()?;
+async fn write_file(path: &Path, content: &[u8]) -> Result<()> {
+    let parent_path = path.parent().unwrap();
 
-        if bytes.starts_with(b"<svg") {
-            svg = bytes.into();
-        } else {
-            i.url = image_to_base64(&image::load_from_memory(&bytes)?).into_bytes();
-        }
+    if parent_path.exists().not() {
+        fs::create_dir_all(parent_path).await?;
     }
 
-    if svg.is_empty() {
-        return Ok(());
-    }
+    File::create(path).await?.write_all(content).await?;
 
-    node.data.borrow_mut().value = NodeValue::HtmlBlock(NodeHtmlBlock {
-        block_type: 7,
-        literal: svg,
-    });
+    debug!("Write File to: {}", path.display());
 
     Ok(())
 }
 
-fn convert_link<'a>(node: &'a AstNode<'a>, articles: &HashMap<String, PathBuf>) -> Result<()> {
-    let mut url = String::new();
-    let mut content = None;
-
-    if let NodeValue::Link(link) = &node.data.borrow().value {
-        url = String::from_utf8_lossy(&link.url).to_string();
-        content = node.first_child();
-    }
-
-    let origin_url = url::Url::parse(&url)?;
+/// The key of the document in `schema.json`, the path without the leading `docs/`.
+fn schema_key(path: &Path) -> String {
+    let mut components = path.components();
+    components.next();
+    components.next();
 
-    let domain = origin_url
-        .domain()
-        .ok_or(Error::Internal(format!("Invalid url: {}", url)))?;
-
-    // Codepen
-    if domain.contains("codepen") {
-        let literal = CODEPEN_IFRAME.replace("{}", &url).into_bytes();
-        node.children().for_each(|node| node.detach());
+    components.collect::<PathBuf>().display().to_string()
+}
 
-        let mut data = node.data.borrow_mut();
-        data.value = NodeValue::HtmlBlock(NodeHtmlBlock {
-            block_type: 6,
-            literal,
-        });
+/// Directories under `docs/public` written by the transform, the `key` of its options
+/// in each namespace, or `default` when not configured.
+fn public_dirs(
+    namespaces: &[Namespace],
+    transform: &str,
+    key: &str,
+    default: &str,
+) -> HashSet<PathBuf> {
+    namespaces
+        .iter()
+        .flat_map(|ns| ns.transforms.iter().flatten())
+        .filter(|config| config.name == transform)
+        .filter_map(|config| config.options.get(key).and_then(serde_yaml::Value::as_str))
+        .chain(Some(default))
+        .map(|dir| dir.trim_matches('/'))
+        // Never `docs/public` itself or anything outside of it
+        .filter(|dir| !dir.is_empty() && !dir.split('/').any(|s| s == ".."))
+        .map(|dir| PathBuf::from("./docs/public").join(dir))
+        .collect()
+}
 
-        return Ok(());
-    }
+/// Minutes to read the document, about 300 Chinese characters per minute.
+fn reading_time(word_count: u64) -> u64 {
+    word_count.div_ceil(WORDS_PER_MINUTE)
+}
 
-    // inner link
-    let path = PathBuf::from(origin_url.path());
+/// Whether the cached document is rendered at the same place with the same config.
+fn is_fresh(entry: &DocCacheEntry, path: &Path, order: usize, options: &NamespaceOptions) -> bool {
+    entry.path == path && entry.order == order && entry.fingerprint == options.fingerprint
+}
 
-    let doc_slug = path
-        .file_name()
-        .ok_or(Error::Internal(format!("Invalid url path: {}", url)))?;
-    let doc_slug = doc_slug.to_str().unwrap().to_string();
+/// What the documents of a namespace are written with.
+struct NamespaceOptions<'a> {
+    transforms: Vec<Box<dyn Transform>>,
+    fields: &'a [FrontmatterField],
+    /// Hash of everything in the config affecting the output, part of the cache key
+    fingerprint: String,
+}
 
-    let path = articles
-        .get(&doc_slug)
-        .ok_or(Error::Internal(format!("No such document: {}", doc_slug)))?;
+/// A document fetched from Yuque, with the body converted into markdown.
+struct FetchedDoc {
+    title: String,
+    /// Url of the document on Yuque
+    source: String,
+    body: String,
+    /// Hash of the body before converted
+    hash: String,
+    author: Option<String>,
+    created_at: String,
+    updated_at: String,
+    word_count: u64,
+    cover: Option<String>,
+}
 
-    let path = path.strip_prefix("./docs").unwrap().display();
-    info!("change url to inner link: {}", path);
-    node.children().for_each(|node| node.detach());
+struct ResolvedNamespace {
+    book_id: i32,
+    title: String,
+    path: String,
+    description: String,
+    entries: Vec<(usize, PathBuf, Entry)>,
+}
 
-    let mut data = node.data.borrow_mut();
-    data.value = NodeValue::Link(NodeLink {
-        url: format!("/{}", path).into_bytes(),
-        title: vec![],
-    });
+enum Entry {
+    /// Document id, its update time and the document if fetched before writing
+    Doc(u32, Option<String>, Option<Box<FetchedDoc>>),
+    /// Title without content in the toc
+    Title(String),
+}
 
-    if let Some(content) = content {
-        node.append(content);
+impl<'a> From<CheckedGeneratorConfig<'a>> for Generator<'a> {
+    fn from(value: CheckedGeneratorConfig<'a>) -> Self {
+        Self::from_config(value)
     }
+}
 
-    Ok(())
+fn problem(diagnostic: Diagnostic) -> Problem {
+    Problem {
+        kind: (&diagnostic.error).into(),
+        document: diagnostic.document,
+        detail: diagnostic.error.to_string(),
+        transform: Some(diagnostic.transform),
+        line: Some(diagnostic.line),
+    }
 }
 
 #[allow(unused)]
@@ -643,7 +1252,7 @@ fn parse_schema(text: &str) -> serde_json::Value {
 
     let lines = text.lines();
     let mut schema = serde_json::json!({});
-    let anchor = Regex::new("<a .*>").unwrap();
+    let anchor = Regex::new(YUQUE_ANCHOR).unwrap();
 
     for line in lines {
         if anchor.is_match(line) || line.is_empty() {
@@ -668,8 +1277,8 @@ fn parse_schema(text: &str) -> serde_json::Value {
     schema
 }
 
-fn filter_schema(text: &str, path: &Path, schemas: &Mutex<HashMap<String, Value>>) -> String {
-    info!("filter schema: {}", path.display());
+fn filter_schema(text: &str, key: &str, schemas: &Mutex<HashMap<String, Value>>) -> String {
+    info!("filter schema: {}", key);
     let schemas = &mut schemas.lock().unwrap();
 
     let regex = Regex::new(r"---").unwrap();
@@ -684,7 +1293,100 @@ fn filter_schema(text: &str, path: &Path, schemas: &Mutex<HashMap<String, Value>
         let content_string = split_result;
 
         let schema = parse_schema(schema_string);
-        schemas.insert(path.display().to_string(), schema);
+        schemas.insert(key.to_string(), schema);
         content_string.join("---")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embed::EmbedRegistry;
+
+    fn generator(cache_dir: &Path) -> Generator<'static> {
+        Generator::from_config(CheckedGeneratorConfig {
+            // Nothing listens on the port, any request to Yuque fails
+            host: "http://127.0.0.1:1".into(),
+            base: "/".into(),
+            token: "token".into(),
+            namespaces: vec![],
+            build_command: "".into(),
+            cache_dir: cache_dir.display().to_string().into(),
+            concurrency: 1,
+            rate_limit: 0,
+            image: Default::default(),
+            sanitize: Default::default(),
+            embeds: EmbedRegistry::new(vec![]).unwrap(),
+            strict: false,
+            markdown: Default::default(),
+        })
+    }
+
+    #[tokio::test]
+    async fn unchanged_documents_use_cache() {
+        let dir = std::env::temp_dir().join(format!("yuque-ssg-doc-cache-{}", std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+
+        let generator = generator(&dir);
+        let client = generator.inner.read().await.client.docs();
+        let options = NamespaceOptions {
+            transforms: vec![],
+            fields: &[],
+            fingerprint: "config".into(),
+        };
+        let path = dir.join("docs/repo/intro.md");
+
+        generator.cache.lock().unwrap().insert(
+            "user/repo",
+            1,
+            DocCacheEntry {
+                updated_at: "2023-01-01".into(),
+                hash: "hash".into(),
+                path: path.clone(),
+                order: 0,
+                schema: None,
+                output: "cached output".into(),
+                problems: vec![],
+                anchors: Default::default(),
+                source: Default::default(),
+                fingerprint: options.fingerprint.clone(),
+                links: Default::default(),
+            },
+        );
+
+        // Not updated, written from the cache without fetching
+        generator
+            .write_markdown(
+                &client,
+                &options,
+                path.clone(),
+                "user/repo",
+                1,
+                0,
+                Some("2023-01-01".into()),
+                None,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "cached output");
+
+        // Updated, fetched from Yuque which is not reachable
+        let updated = generator
+            .write_markdown(
+                &client,
+                &options,
+                path.clone(),
+                "user/repo",
+                1,
+                0,
+                Some("2023-02-01".into()),
+                None,
+            )
+            .await;
+
+        assert!(updated.is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,355 @@
+//! 图片的处理与保存
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::Cursor,
+    ops::Not,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use base64::Engine;
+use image::{imageops::FilterType, io::Reader, ImageFormat};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cache::hash,
+    config::{ImageConfig, OutputFormat},
+    error::{Error, Result},
+};
+
+const IMAGE_CACHE_INDEX: &str = "index.json";
+
+/// Downloaded images keyed by url, the least recently used images are evicted
+/// when the total size exceeds `max_size`.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct ImageCache {
+    #[serde(skip)]
+    dir: PathBuf,
+    #[serde(skip)]
+    max_size: u64,
+    entries: HashMap<String, ImageCacheEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ImageCacheEntry {
+    pub file: String,
+    pub size: u64,
+    pub content_type: Option<String>,
+    pub fetched_at: u64,
+    pub accessed_at: u64,
+}
+
+impl ImageCache {
+    /// Load the cache from `dir`, `max_size` is in bytes.
+    pub fn load(dir: impl AsRef<Path>, max_size: u64) -> Self {
+        let dir = dir.as_ref().to_path_buf();
+
+        let cache = match File::open(dir.join(IMAGE_CACHE_INDEX)) {
+            Ok(file) => serde_json::from_reader(file).unwrap_or_else(|e| {
+                warn!("Can not read the image cache due to {}.", e);
+                ImageCache::default()
+            }),
+            Err(_) => ImageCache::default(),
+        };
+
+        info!("Load image cache from: {}", dir.display());
+
+        Self {
+            dir,
+            max_size,
+            ..cache
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if self.dir.exists().not() {
+            fs::create_dir_all(&self.dir)?;
+        }
+
+        serde_json::to_writer(File::create(self.dir.join(IMAGE_CACHE_INDEX))?, self)?;
+
+        debug!("Save image cache to: {}", self.dir.display());
+
+        Ok(())
+    }
+
+    pub fn get(&mut self, url: &str) -> Option<Vec<u8>> {
+        let entry = self.entries.get_mut(url)?;
+
+        match fs::read(self.dir.join(&entry.file)) {
+            Ok(bytes) => {
+                debug!("Use cached image: {}", url);
+                entry.accessed_at = now();
+                Some(bytes)
+            }
+            Err(_) => {
+                self.entries.remove(url);
+                None
+            }
+        }
+    }
+
+    pub fn insert(&mut self, url: &str, bytes: &[u8], content_type: Option<String>) -> Result<()> {
+        if self.dir.exists().not() {
+            fs::create_dir_all(&self.dir)?;
+        }
+
+        let file = hash(url);
+        fs::write(self.dir.join(&file), bytes)?;
+
+        let now = now();
+
+        self.entries.insert(
+            url.to_string(),
+            ImageCacheEntry {
+                file,
+                size: bytes.len() as u64,
+                content_type,
+                fetched_at: now,
+                accessed_at: now,
+            },
+        );
+
+        self.evict();
+
+        Ok(())
+    }
+
+    fn evict(&mut self) {
+        let mut total: u64 = self.entries.values().map(|e| e.size).sum();
+
+        if total <= self.max_size {
+            return;
+        }
+
+        let mut entries = self
+            .entries
+            .iter()
+            .map(|(url, e)| (e.accessed_at, url.clone()))
+            .collect::<Vec<_>>();
+        entries.sort();
+
+        for (_, url) in entries {
+            if total <= self.max_size {
+                break;
+            }
+
+            if let Some(entry) = self.entries.remove(&url) {
+                debug!("Evict cached image: {}", url);
+                fs::remove_file(self.dir.join(&entry.file)).ok();
+                total -= entry.size;
+            }
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+pub struct ProcessedImage {
+    pub bytes: Vec<u8>,
+    pub format: ImageFormat,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl ProcessedImage {
+    pub fn extension(&self) -> &'static str {
+        extension(self.format)
+    }
+
+    pub fn to_data_uri(&self) -> String {
+        data_uri(&self.bytes, mime_type(self.extension()))
+    }
+}
+
+/// Resize and convert the image as configured, the image is kept as is when nothing to do.
+pub fn process_image(bytes: &[u8], config: &ImageConfig) -> Result<ProcessedImage> {
+    let reader = Reader::new(Cursor::new(bytes)).with_guessed_format()?;
+    let format = reader
+        .format()
+        .ok_or_else(|| Error::Image("Unknown image format".into()))?;
+    let (width, height) = reader.into_dimensions()?;
+
+    let target = match config.format {
+        OutputFormat::Original => format,
+        // Converting loses the animation of gif
+        _ if format == ImageFormat::Gif => format,
+        OutputFormat::Webp => ImageFormat::WebP,
+        OutputFormat::Avif => ImageFormat::Avif,
+    };
+
+    let resize = config
+        .max_width
+        .filter(|max_width| width > *max_width && format != ImageFormat::Gif);
+
+    if resize.is_none() && target == format {
+        return Ok(ProcessedImage {
+            bytes: bytes.to_vec(),
+            format,
+            width,
+            height,
+        });
+    }
+
+    let mut image = image::load_from_memory_with_format(bytes, format)?;
+
+    if let Some(max_width) = resize {
+        debug!("Resize image from {}px to {}px.", width, max_width);
+        image = image.resize(max_width, u32::MAX, FilterType::Lanczos3);
+    }
+
+    let mut output = vec![];
+    image.write_to(&mut Cursor::new(&mut output), target)?;
+
+    info!("Convert image from {:?} to {:?}.", format, target);
+
+    Ok(ProcessedImage {
+        bytes: output,
+        format: target,
+        width: image.width(),
+        height: image.height(),
+    })
+}
+
+/// Save the file under `docs/public/{assets}`, named by its content hash,
+/// return the url of the saved file.
+pub fn save_asset(bytes: &[u8], ext: &str, assets: &str) -> Result<String> {
+    let name = format!("{}.{}", &hash(bytes)[..16], ext);
+    let assets = assets.trim_matches('/');
+    let dir = PathBuf::from("docs/public").join(assets);
+
+    if dir.exists().not() {
+        std::fs::create_dir_all(&dir)?;
+    }
+
+    let path = dir.join(&name);
+
+    if path.exists().not() {
+        std::fs::write(&path, bytes)?;
+        info!("Save asset to: {}", path.display());
+    }
+
+    Ok(format!("/{}/{}", assets, name))
+}
+
+pub fn data_uri(bytes: &[u8], mime: &str) -> String {
+    info!("Convert image to base64 string.");
+
+    let res_base64 = base64::prelude::BASE64_STANDARD.encode(bytes);
+    format!("data:{};base64,{}", mime, res_base64)
+}
+
+pub fn extension(format: ImageFormat) -> &'static str {
+    format.extensions_str().first().copied().unwrap_or("bin")
+}
+
+pub fn mime_type(ext: &str) -> &'static str {
+    match ext {
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "bmp" => "image/bmp",
+        "ico" => "image/x-icon",
+        "tif" | "tiff" => "image/tiff",
+        "svg" => "image/svg+xml",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Whether the image is a svg, which may start with a BOM, the xml declaration,
+/// the doctype and comments before `<svg`.
+pub fn is_svg(bytes: &[u8]) -> bool {
+    let bytes = bytes.strip_prefix(b"\xef\xbb\xbf").unwrap_or(bytes);
+
+    // Not a text file, such as png or jpeg
+    if bytes.iter().find(|b| !b.is_ascii_whitespace()) != Some(&b'<') {
+        return false;
+    }
+
+    let text = String::from_utf8_lossy(bytes);
+    let mut rest = text.trim_start();
+
+    loop {
+        let end = if rest.starts_with("<?") {
+            "?>"
+        } else if rest.starts_with("<!--") {
+            "-->"
+        } else if rest.starts_with("<!") {
+            ">"
+        } else {
+            break;
+        };
+
+        match rest.find(end) {
+            Some(i) => rest = rest[i + end.len()..].trim_start(),
+            None => return false,
+        }
+    }
+
+    rest.starts_with("<svg")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evict_least_recently_used() {
+        let dir =
+            std::env::temp_dir().join(format!("yuque-ssg-image-cache-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        let mut cache = ImageCache::load(&dir, 10);
+
+        cache.insert("a", b"aaaa", None).unwrap();
+        cache.insert("b", b"bbbb", None).unwrap();
+
+        // `b` is inserted later but `a` is used more recently
+        cache.entries.get_mut("a").unwrap().accessed_at = 1;
+        cache.entries.get_mut("b").unwrap().accessed_at = 2;
+        assert_eq!(cache.get("a"), Some(b"aaaa".to_vec()));
+
+        let b = dir.join(&cache.entries["b"].file);
+        cache.insert("c", b"cccc", None).unwrap();
+
+        assert!(cache.entries.contains_key("a"));
+        assert!(cache.entries.contains_key("c"));
+        assert!(!cache.entries.contains_key("b"));
+        assert!(!b.exists());
+        assert_eq!(cache.entries.values().map(|e| e.size).sum::<u64>(), 8);
+
+        // Larger than the cache, the oldest ones are evicted until it fits
+        cache.entries.get_mut("a").unwrap().accessed_at = 1;
+        cache.entries.get_mut("c").unwrap().accessed_at = 1;
+        cache.insert("d", b"dddddddd", None).unwrap();
+
+        assert_eq!(cache.entries.keys().collect::<Vec<_>>(), vec!["d"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn svg_with_prolog() {
+        let svg = concat!(
+            "\u{feff}<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<!-- Generator: Sketch -->\n",
+            "<!DOCTYPE svg PUBLIC \"-//W3C//DTD SVG 1.1//EN\" ",
+            "\"http://www.w3.org/Graphics/SVG/1.1/DTD/svg11.dtd\">\n",
+            "<svg xmlns=\"http://www.w3.org/2000/svg\"><rect width=\"1\" height=\"1\"/></svg>",
+        );
+
+        assert!(is_svg(svg.as_bytes()));
+        assert!(is_svg(b"  <svg></svg>"));
+        assert!(!is_svg(b"<?xml version=\"1.0\"?><html></html>"));
+        assert!(!is_svg(b"\x89PNG\r\n\x1a\n"));
+    }
+}
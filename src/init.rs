@@ -8,7 +8,7 @@
 //!         + 如果不存在则写入默认的
 //!     3. 安装依赖
 //!         + 失败则重试3次
-//! 3. 启动服务器 todo
+//! 3. 生成、构建并启动服务器
 //!
 
 use std::path::{Path, PathBuf};
@@ -33,9 +33,9 @@ impl<'a> Config<'a> {
     pub fn read_config(
         path: impl AsRef<Path>,
     ) -> Result<(CheckedSiteConfig<'a>, CheckedGeneratorConfig<'a>)> {
-        let config_file = std::fs::File::open(path)?;
+        let config_file = std::fs::File::open(&path)?;
 
-        info!("Read config from: `config.yml`");
+        info!("Read config from: `{}`", path.as_ref().display());
 
         let config: Config = serde_yaml::from_reader(config_file)?;
 
@@ -187,9 +187,38 @@ impl<'a> CheckedSiteConfig<'a> {
     }
 }
 
-pub async fn initialize<'a>() -> Result<((Data<Notify>, Data<RwLock<i32>>), CheckedSiteConfig<'a>)>
-{
-    let (site, gen) = Config::read_config("config.yml")?;
+pub async fn generate(config: impl AsRef<Path>) -> Result<()> {
+    let (_, gen) = Config::read_config(config)?;
+
+    let generator: Generator = gen.into();
+
+    generator.generate_all().await
+}
+
+pub async fn build(config: impl AsRef<Path>) -> Result<()> {
+    let (site, gen) = Config::read_config(config)?;
+
+    let generator: Generator = gen.into();
+
+    generator.generate_all().await?;
+
+    site.check_env().await?;
+
+    generator.build().await
+}
+
+pub async fn clean(config: impl AsRef<Path>) -> Result<()> {
+    let (_, gen) = Config::read_config(config)?;
+
+    let generator: Generator = gen.into();
+
+    generator.clean_all().await
+}
+
+pub async fn initialize<'a>(
+    config: impl AsRef<Path>,
+) -> Result<((Data<Notify>, Data<RwLock<i32>>), CheckedSiteConfig<'a>)> {
+    let (site, gen) = Config::read_config(config)?;
 
     let generator: Generator = gen.into();
 
@@ -197,14 +226,17 @@ pub async fn initialize<'a>() -> Result<((Data<Notify>, Data<RwLock<i32>>), Chec
 
     site.check_env().await?;
 
-    generator.build().await?;
+    if let Err(e) = generator.build().await {
+        warn!("{}", e);
+    }
 
     let rebuild = web::Data::new(Notify::new());
     let rebuild_info = web::Data::new(RwLock::new(0));
     let rebuild_cloned = rebuild.clone();
     let rebuild_info_cloned = rebuild_info.clone();
 
-    tokio::spawn(async move {
+    // The generation holds the `!Send` markdown AST across `.await`
+    actix_web::rt::spawn(async move {
         loop {
             rebuild.notified().await;
             let info = *rebuild_info.read().await;
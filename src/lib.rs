@@ -1,16 +1,26 @@
+pub mod cache;
 pub mod config;
+pub mod convert;
+pub mod embed;
 pub mod error;
 pub mod generator;
 pub mod handler;
+pub mod images;
 pub mod init;
+pub mod limiter;
 pub mod log;
+pub mod report;
+pub mod sanitize;
 pub mod toc;
+pub mod transform;
 
 mod formatter;
 
 pub const USER_AGENT: &str = "Mozilla/5.0 (Windows NT 6.1; WOW64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/31.0.1650.63 Safari/537.36";
 pub const CODEPEN_IFRAME: &str = r#"<iframe height="400" style="width: 100%;" scrolling="no" title="Untitled" src="{}" frameborder="no" loading="lazy" allowtransparency="true" allowfullscreen="true"></iframe>
 "#;
+pub const EMBED_IFRAME: &str = r#"<iframe height="450" style="width: 100%;" src="{}" frameborder="no" loading="lazy" allowfullscreen="true"></iframe>
+"#;
 
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -0,0 +1,81 @@
+//! 限制请求语雀 API 的频率
+
+use std::time::Duration;
+
+use tokio::{
+    sync::Mutex,
+    time::{sleep_until, Instant},
+};
+
+pub struct RateLimiter {
+    interval: Option<Duration>,
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    /// Allow at most `per_second` requests per second, `0` means no limit.
+    pub fn new(per_second: u32) -> Self {
+        let interval = (per_second > 0).then(|| Duration::from_secs(1) / per_second);
+
+        Self {
+            interval,
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Wait until the next request is allowed.
+    pub async fn wait(&self) {
+        let Some(interval) = self.interval else {
+            return;
+        };
+
+        let mut next = self.next.lock().await;
+        let now = Instant::now();
+
+        if *next > now {
+            sleep_until(*next).await;
+        }
+
+        *next = (*next).max(now) + interval;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::future::join_all;
+
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn spacing() {
+        let limiter = &RateLimiter::new(4);
+        let start = Instant::now();
+
+        let waited = join_all((0..3).map(|_| async move {
+            limiter.wait().await;
+            start.elapsed()
+        }))
+        .await;
+
+        assert_eq!(waited, [0, 250, 500].map(Duration::from_millis).to_vec());
+
+        // No burst after idle, the next request is spaced from now
+        sleep_until(start + Duration::from_secs(2)).await;
+        limiter.wait().await;
+        limiter.wait().await;
+
+        assert_eq!(start.elapsed(), Duration::from_millis(2250));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unlimited() {
+        let limiter = RateLimiter::new(0);
+        let start = Instant::now();
+
+        for _ in 0..10 {
+            limiter.wait().await;
+        }
+
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+}
@@ -2,30 +2,85 @@ use actix_files::NamedFile;
 use actix_web::{
     dev::{fn_service, ServiceRequest, ServiceResponse},
     middleware::Logger,
+    web::Data,
     App, HttpServer,
 };
+use clap::{Parser, Subcommand};
+use tokio::sync::{Notify, RwLock};
 
 use yuque_ssg::{
+    config::{CheckedSiteConfig, Config},
     handler::{static_file, webhook},
-    init::initialize,
+    init::{build, clean, generate, initialize},
     log::init_logger,
 };
 
-use std::error::Error;
+use std::{error::Error, path::PathBuf};
+
+#[derive(Parser)]
+#[command(version, about = "Generate a static site from Yuque knowledge bases")]
+struct Cli {
+    /// Path of the config file
+    #[arg(short, long, global = true, default_value = "config.yml")]
+    config: PathBuf,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Fetch the documents and write the markdown files
+    Generate,
+    /// Generate the markdown files and build the static site once
+    Build,
+    /// Serve the existing static site
+    Serve,
+    /// Remove the generated files and the build output
+    Clean,
+    /// Generate, build and serve, rebuilding when the webhook is called (default)
+    Watch,
+}
 
 #[actix_web::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     init_logger();
 
-    let ((rebuild, rebuild_info), config) = initialize().await?;
+    let cli = Cli::parse();
+
+    match cli.command.unwrap_or(Command::Watch) {
+        Command::Generate => generate(&cli.config).await?,
+        Command::Build => build(&cli.config).await?,
+        Command::Clean => clean(&cli.config).await?,
+        Command::Serve => {
+            let (site, _) = Config::read_config(&cli.config)?;
+            serve(&site, None).await?;
+        }
+        Command::Watch => {
+            let (rebuild, site) = initialize(&cli.config).await?;
+            serve(&site, Some(rebuild)).await?;
+        }
+    }
 
+    Ok(())
+}
+
+async fn serve(
+    site: &CheckedSiteConfig<'_>,
+    rebuild: Option<(Data<Notify>, Data<RwLock<i32>>)>,
+) -> std::io::Result<()> {
     HttpServer::new(move || {
-        App::new()
-            .wrap(Logger::new("%r %s"))
-            .app_data(rebuild.clone())
-            .app_data(rebuild_info.clone())
-            .service(webhook)
-            .service(static_file("/"))
+        let app = App::new().wrap(Logger::new("%r %s"));
+
+        let app = match rebuild.clone() {
+            Some((rebuild, rebuild_info)) => app
+                .app_data(rebuild)
+                .app_data(rebuild_info)
+                .service(webhook),
+            None => app,
+        };
+
+        app.service(static_file("/"))
             .default_service(fn_service(|req: ServiceRequest| async {
                 let (req, _) = req.into_parts();
                 let file = NamedFile::open_async("docs/.vitepress/dist/404.html").await?;
@@ -33,9 +88,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 Ok(ServiceResponse::new(req, res))
             }))
     })
-    .bind(format!("{}:{}", config.host, config.port))?
+    .bind(format!("{}:{}", site.host, site.port))?
     .run()
-    .await?;
-
-    Ok(())
+    .await
 }
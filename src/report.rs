@@ -0,0 +1,105 @@
+//! 生成过程中遇到的问题，例如无法解析的语雀链接和下载失败的图片
+
+use std::{collections::BTreeMap, fs::File, path::Path};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum ProblemKind {
+    /// Yuque link which can not be resolved to a generated document
+    BrokenLink,
+    /// Image which can not be downloaded or processed
+    Image,
+    /// Attachment which can not be downloaded
+    Attachment,
+    /// Content which can not be parsed
+    Parse,
+    /// Document which can not be fetched or written
+    Document,
+}
+
+impl From<&Error> for ProblemKind {
+    fn from(value: &Error) -> Self {
+        match value {
+            Error::BrokenLink(_) => Self::BrokenLink,
+            Error::Image(_) | Error::Reqwest(_) => Self::Image,
+            Error::Attachment(_) => Self::Attachment,
+            Error::InvalidUrl(_) | Error::CantParse(_) => Self::Parse,
+            _ => Self::Document,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Problem {
+    pub kind: ProblemKind,
+    pub document: String,
+    pub detail: String,
+    /// Name of the transform which reports the problem
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transform: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line: Option<u32>,
+}
+
+#[derive(Serialize, Default, Debug)]
+pub struct Report {
+    problems: Vec<Problem>,
+}
+
+impl Report {
+    pub fn push(&mut self, kind: ProblemKind, document: impl ToString, detail: impl ToString) {
+        self.problems.push(Problem {
+            kind,
+            document: document.to_string(),
+            detail: detail.to_string(),
+            transform: None,
+            line: None,
+        });
+    }
+
+    pub fn extend(&mut self, problems: impl IntoIterator<Item = Problem>) {
+        self.problems.extend(problems);
+    }
+
+    pub fn clear(&mut self) {
+        self.problems.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.problems.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    pub fn write_to(&self, path: impl AsRef<Path>) -> Result<()> {
+        serde_json::to_writer_pretty(File::create(&path)?, self)?;
+
+        info!("Write build report to: {}", path.as_ref().display());
+
+        Ok(())
+    }
+
+    pub fn log_summary(&self) {
+        if self.is_empty() {
+            info!("No problem found during the build.");
+            return;
+        }
+
+        let mut count = BTreeMap::new();
+        for problem in self.problems.iter() {
+            *count.entry(problem.kind).or_insert(0) += 1;
+        }
+
+        warn!("Found {} problems during the build:", self.len());
+        for (kind, count) in count {
+            warn!("  {:?}: {}", kind, count);
+        }
+    }
+}
@@ -0,0 +1,518 @@
+//! 清理来自语雀的不可信内容
+
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    iter::zip,
+    sync::OnceLock,
+};
+
+use ammonia::{Builder, UrlRelative};
+use comrak::nodes::{AstNode, NodeValue};
+use log::{debug, warn};
+use regex::Regex;
+
+use crate::{config::SanitizeConfig, error::Result};
+
+/// Stands for the markdown between the raw html nodes when they are cleaned as a whole,
+/// a private use character which is not expected in the documents.
+const MARKER: char = '\u{E000}';
+
+/// Allowlist based sanitizer for the raw html in the documents.
+pub struct HtmlSanitizer<'a> {
+    builder: Builder<'a>,
+    tags: HashSet<&'a str>,
+    tag: Regex,
+}
+
+impl<'a> HtmlSanitizer<'a> {
+    pub fn new(config: &'a SanitizeConfig) -> Self {
+        let mut builder = Builder::default();
+
+        builder
+            .add_tags(&["font", "mark", "details", "summary"])
+            .add_tag_attributes("a", &["name"])
+            .add_tag_attributes("font", &["color", "style"])
+            .add_tag_attributes("span", &["style"])
+            .add_tags(config.tags.iter().map(String::as_str));
+
+        for (tag, attributes) in config.attributes.iter() {
+            builder.add_tag_attributes(tag.as_str(), attributes.iter().map(String::as_str));
+        }
+
+        let tags = builder.clone_tags();
+
+        Self {
+            builder,
+            tags,
+            tag: Regex::new(r"^<(/?)([A-Za-z][A-Za-z0-9-]*)").unwrap(),
+        }
+    }
+
+    /// Clean the html fragments as one piece of html, so the tags opened in one fragment
+    /// and closed in another are kept. `None` if the fragments can not be told apart after
+    /// cleaned, such as the markdown between them is moved out of a `<table>`.
+    pub fn clean_fragments(&self, fragments: &[String]) -> Option<Vec<String>> {
+        let marker = |i: usize| format!("{}{}{}", MARKER, i, MARKER);
+
+        let html = fragments
+            .iter()
+            .enumerate()
+            .map(|(i, fragment)| match i {
+                0 => fragment.clone(),
+                i => format!("{}{}", marker(i), fragment),
+            })
+            .collect::<String>();
+
+        let cleaned = self.builder.clean(&html).to_string();
+
+        let mut cleaned_fragments = Vec::with_capacity(fragments.len());
+        let mut rest = cleaned.as_str();
+
+        for i in 1..fragments.len() {
+            let (fragment, next) = rest.split_once(&marker(i))?;
+            cleaned_fragments.push(fragment.to_string());
+            rest = next;
+        }
+
+        cleaned_fragments.push(rest.to_string());
+
+        cleaned_fragments
+            .iter()
+            .all(|fragment| !fragment.contains(MARKER))
+            .then_some(cleaned_fragments)
+    }
+
+    pub fn clean_block(&self, html: &str) -> String {
+        self.builder.clean(html).to_string()
+    }
+
+    /// Comrak splits inline html into single tags, so each tag is cleaned on its own.
+    pub fn clean_inline(&self, html: &str) -> String {
+        let Some(caps) = self.tag.captures(html) else {
+            // Comments, declarations and processing instructions
+            return String::new();
+        };
+
+        let name = caps[2].to_lowercase();
+
+        if !self.tags.contains(name.as_str()) {
+            return String::new();
+        }
+
+        if !caps[1].is_empty() {
+            return format!("</{}>", name);
+        }
+
+        let cleaned = self.builder.clean(html).to_string();
+        let closing = format!("</{}>", name);
+
+        cleaned
+            .strip_suffix(&closing)
+            .map(String::from)
+            .unwrap_or(cleaned)
+    }
+}
+
+/// Escape the value to be put in a quoted attribute of html.
+pub fn escape_attribute(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Clean the raw html from Yuque in the document as a whole, must run before the transforms
+/// producing trusted html, such as the embeds and the inlined svg.
+pub fn sanitize_html<'a>(root: &'a AstNode<'a>, sanitizer: &HtmlSanitizer) -> Result<()> {
+    let nodes = root
+        .descendants()
+        .filter(|node| {
+            matches!(
+                node.data.borrow().value,
+                NodeValue::HtmlBlock(_) | NodeValue::HtmlInline(_)
+            )
+        })
+        .collect::<Vec<_>>();
+
+    if nodes.is_empty() {
+        return Ok(());
+    }
+
+    let fragments = nodes
+        .iter()
+        .map(|node| match &node.data.borrow().value {
+            NodeValue::HtmlBlock(block) => String::from_utf8_lossy(&block.literal).to_string(),
+            NodeValue::HtmlInline(literal) => String::from_utf8_lossy(literal).to_string(),
+            _ => unreachable!(),
+        })
+        .collect::<Vec<_>>();
+
+    let cleaned = sanitizer.clean_fragments(&fragments).unwrap_or_else(|| {
+        warn!("Can not clean the html as a whole, clean each of them instead.");
+
+        zip(&nodes, &fragments)
+            .map(|(node, html)| match node.data.borrow().value {
+                NodeValue::HtmlBlock(_) => sanitizer.clean_block(html),
+                _ => sanitizer.clean_inline(html),
+            })
+            .collect()
+    });
+
+    for (node, (html, cleaned)) in zip(nodes, zip(fragments, cleaned)) {
+        match &mut node.data.borrow_mut().value {
+            NodeValue::HtmlBlock(block) => {
+                let mut cleaned = cleaned.trim_end().to_string();
+
+                if cleaned != html.trim_end() {
+                    debug!("Sanitize html block: {}", html.trim_end());
+                }
+
+                cleaned.push('\n');
+                block.literal = cleaned.into_bytes();
+            }
+            NodeValue::HtmlInline(literal) => {
+                if cleaned != html {
+                    debug!("Sanitize inline html: {}", html);
+                }
+
+                *literal = cleaned.into_bytes();
+            }
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
+
+/// Tags of the svg kept by [`sanitize_svg`], the others are removed with their attributes.
+const SVG_TAGS: &[&str] = &[
+    "svg",
+    "g",
+    "defs",
+    "symbol",
+    "use",
+    "title",
+    "desc",
+    "path",
+    "rect",
+    "circle",
+    "ellipse",
+    "line",
+    "polyline",
+    "polygon",
+    "text",
+    "tspan",
+    "textPath",
+    "linearGradient",
+    "radialGradient",
+    "stop",
+    "pattern",
+    "clipPath",
+    "mask",
+    "marker",
+    "filter",
+    "feBlend",
+    "feColorMatrix",
+    "feComposite",
+    "feDropShadow",
+    "feFlood",
+    "feGaussianBlur",
+    "feMerge",
+    "feMergeNode",
+    "feOffset",
+];
+
+/// Attributes of the svg kept on any tag, no event handler or `style` is allowed.
+const SVG_ATTRIBUTES: &[&str] = &[
+    "xmlns",
+    // `xmlns:xlink`, the parser keeps the local name only
+    "xlink",
+    "version",
+    "id",
+    "class",
+    "href",
+    "viewBox",
+    "preserveAspectRatio",
+    "transform",
+    "x",
+    "y",
+    "x1",
+    "y1",
+    "x2",
+    "y2",
+    "cx",
+    "cy",
+    "r",
+    "rx",
+    "ry",
+    "fx",
+    "fy",
+    "dx",
+    "dy",
+    "d",
+    "points",
+    "width",
+    "height",
+    "rotate",
+    "offset",
+    "fill",
+    "fill-opacity",
+    "fill-rule",
+    "stroke",
+    "stroke-width",
+    "stroke-opacity",
+    "stroke-linecap",
+    "stroke-linejoin",
+    "stroke-dasharray",
+    "stroke-dashoffset",
+    "stroke-miterlimit",
+    "opacity",
+    "color",
+    "display",
+    "visibility",
+    "clip-path",
+    "clip-rule",
+    "clipPathUnits",
+    "mask",
+    "maskUnits",
+    "maskContentUnits",
+    "filter",
+    "filterUnits",
+    "primitiveUnits",
+    "marker-start",
+    "marker-mid",
+    "marker-end",
+    "markerWidth",
+    "markerHeight",
+    "markerUnits",
+    "refX",
+    "refY",
+    "orient",
+    "stop-color",
+    "stop-opacity",
+    "gradientUnits",
+    "gradientTransform",
+    "spreadMethod",
+    "patternUnits",
+    "patternContentUnits",
+    "patternTransform",
+    "font-family",
+    "font-size",
+    "font-style",
+    "font-weight",
+    "text-anchor",
+    "text-decoration",
+    "dominant-baseline",
+    "alignment-baseline",
+    "letter-spacing",
+    "textLength",
+    "lengthAdjust",
+    "startOffset",
+    "in",
+    "in2",
+    "result",
+    "mode",
+    "type",
+    "values",
+    "operator",
+    "stdDeviation",
+    "flood-color",
+    "flood-opacity",
+];
+
+fn svg_sanitizer() -> &'static Builder<'static> {
+    static SANITIZER: OnceLock<Builder<'static>> = OnceLock::new();
+
+    SANITIZER.get_or_init(|| {
+        let mut builder = Builder::default();
+
+        builder
+            .tags(SVG_TAGS.iter().copied().collect())
+            .tag_attributes(HashMap::new())
+            .generic_attributes(SVG_ATTRIBUTES.iter().copied().collect())
+            // Only the references inside the svg, such as `href="#id"`
+            .url_schemes(HashSet::new())
+            .url_relative(UrlRelative::Custom(Box::new(fragment_only)))
+            .attribute_filter(local_resources_only)
+            .link_rel(None)
+            .strip_comments(true);
+
+        builder
+    })
+}
+
+fn fragment_only(url: &str) -> Option<Cow<'_, str>> {
+    url.starts_with('#').then_some(Cow::Borrowed(url))
+}
+
+/// Presentation attributes, such as `fill="url(...)"`, may load external resources.
+fn local_resources_only<'u>(_: &str, _: &str, value: &'u str) -> Option<Cow<'u, str>> {
+    let lowercase = value.to_ascii_lowercase();
+
+    let is_local = lowercase.match_indices("url(").all(|(i, _)| {
+        lowercase[i + 4..]
+            .trim_start_matches(|c: char| c.is_whitespace() || c == '\'' || c == '"')
+            .starts_with('#')
+    });
+
+    is_local.then_some(Cow::Borrowed(value))
+}
+
+/// Keep only the allowed tags and attributes of the svg, which is parsed as html like
+/// the browsers do, so no script, event handler or external reference is left.
+/// Returns `None` when nothing of the svg is left, which should not be inlined.
+pub fn sanitize_svg(svg: &str) -> Option<String> {
+    // The xml declaration and the doctype are dropped as the comments
+    let cleaned = svg_sanitizer()
+        .clean(svg.trim_start_matches('\u{feff}'))
+        .to_string();
+
+    if !cleaned.trim_start().starts_with("<svg") {
+        warn!("Svg is empty after sanitized.");
+        return None;
+    }
+
+    Some(cleaned)
+}
+
+#[cfg(test)]
+mod tests {
+    use comrak::{format_commonmark, parse_document, Arena, ComrakOptions};
+
+    use super::*;
+    use crate::convert::{render_table, Cell};
+
+    fn sanitize(markdown: &str) -> String {
+        let arena = Arena::new();
+        let options = ComrakOptions::default();
+        let root = parse_document(&arena, markdown, &options);
+
+        let config = SanitizeConfig::default();
+        sanitize_html(root, &HtmlSanitizer::new(&config)).unwrap();
+
+        let mut output = vec![];
+        format_commonmark(root, &options, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn html_around_markdown() {
+        let output =
+            sanitize("<details>\n<summary>More</summary>\n\nHidden **text**\n\n</details>\n");
+
+        assert!(output.contains("<details>\n<summary>More</summary>"));
+        assert!(output.contains("Hidden **text**"));
+        assert!(output.contains("</details>"));
+    }
+
+    #[test]
+    fn merged_table() {
+        let mut merged = Cell::new("a");
+        merged.colspan = 2;
+
+        let output = sanitize(&render_table(&[
+            vec![merged],
+            vec![Cell::new("**b**"), Cell::new("c")],
+        ]));
+
+        assert!(output.contains(r#"<td colspan="2">"#));
+        assert!(output.contains("**b**"));
+        assert!(output.contains("</td>"));
+        assert!(output.contains("</table>"));
+    }
+
+    #[test]
+    fn unsafe_html() {
+        let output = sanitize(
+            "<div onclick=\"alert(1)\">hi</div>\n\n<script>alert(2)</script>\n\n<a href=\"javascript:alert(3)\">x</a>\n",
+        );
+
+        assert!(output.contains("<div>hi</div>"));
+        assert!(!output.contains("alert"));
+    }
+
+    #[test]
+    fn inline_html() {
+        let output = sanitize("a <span style=\"color: red\" onclick=\"x\">b</span> <iframe>c\n");
+
+        assert!(output.contains(r#"<span style="color: red">b</span>"#));
+        assert!(!output.contains("onclick"));
+        assert!(!output.contains("iframe"));
+    }
+
+    #[test]
+    fn svg_event_handler_without_whitespace() {
+        let svg = sanitize_svg("<svg/onload=alert(1)><rect width=\"1\"/></svg>").unwrap();
+
+        assert!(!svg.contains("onload"));
+        assert!(svg.contains(r#"<rect width="1">"#));
+    }
+
+    #[test]
+    fn svg_entity_encoded_url() {
+        let svg = sanitize_svg(r#"<svg><use href="&#106;avascript:alert(1)"/></svg>"#).unwrap();
+
+        assert!(!svg.contains("javascript"));
+        assert!(!svg.contains("href"));
+    }
+
+    #[test]
+    fn svg_external_references() {
+        let svg = sanitize_svg(
+            r##"<svg><use xlink:href="https://example.com/a.svg#a"/><rect fill="url(https://example.com/a)"/><rect fill="url(#g)"/><use href="#a"/></svg>"##,
+        )
+        .unwrap();
+
+        assert!(!svg.contains("example.com"));
+        assert!(svg.contains(r##"fill="url(#g)""##));
+        assert!(svg.contains(r##"href="#a""##));
+    }
+
+    #[test]
+    fn svg_scripts_and_foreign_objects() {
+        let svg = sanitize_svg(
+            "<svg><script>alert(1)</script><foreignObject><iframe src=\"https://example.com\"></iframe></foreignObject><style>rect{}</style></svg>",
+        )
+        .unwrap();
+
+        assert!(!svg.contains("alert"));
+        assert!(!svg.contains("iframe"));
+        assert!(!svg.contains("foreignObject"));
+        assert!(!svg.contains("rect{}"));
+    }
+
+    #[test]
+    fn svg_keeps_case_sensitive_names() {
+        let svg = sanitize_svg(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 1 1"><linearGradient id="g"><stop offset="0"/></linearGradient></svg>"#,
+        )
+        .unwrap();
+
+        assert!(svg.contains(r#"xmlns="http://www.w3.org/2000/svg""#));
+        assert!(svg.contains(r#"viewBox="0 0 1 1""#));
+        assert!(svg.contains("<linearGradient"));
+    }
+
+    #[test]
+    fn svg_with_prolog() {
+        let svg = sanitize_svg(concat!(
+            "\u{feff}<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<!-- Generator: Sketch -->\n",
+            "<!DOCTYPE svg PUBLIC \"-//W3C//DTD SVG 1.1//EN\" ",
+            "\"http://www.w3.org/Graphics/SVG/1.1/DTD/svg11.dtd\">\n",
+            "<svg><rect width=\"1\" height=\"1\"/></svg>",
+        ))
+        .unwrap();
+
+        assert!(svg.trim_start().starts_with("<svg"));
+        assert!(!svg.contains("<?xml"));
+    }
+
+    #[test]
+    fn svg_without_svg() {
+        assert_eq!(sanitize_svg("<script>alert(1)</script>"), None);
+    }
+}
@@ -30,7 +30,7 @@ pub fn generate_doc_sidebar(doc_dir: impl AsRef<Path>) -> Result<()> {
 
         debug!("Find file: {}", file_name);
 
-        if file_type.is_dir() && !file_name.starts_with('.') && !file_name.starts_with('_') {
+        if file_type.is_dir() && is_namespace_dir(&file_name) {
             let mut json = walk(file.path(), file_name.to_string())?;
             json.sort_by_key(|v| v.order);
             let name = format!("/{}/", file_name.to_lowercase());
@@ -44,6 +44,12 @@ pub fn generate_doc_sidebar(doc_dir: impl AsRef<Path>) -> Result<()> {
     Ok(())
 }
 
+/// Whether the directory under `docs` is a generated knowledge base,
+/// hidden directories, `_` prefixed directories and `public` are not.
+pub(crate) fn is_namespace_dir(file_name: &str) -> bool {
+    !file_name.starts_with('.') && !file_name.starts_with('_') && file_name != "public"
+}
+
 fn walk(dir: impl AsRef<Path>, base: impl AsRef<Path>) -> Result<Vec<SidebarItem>> {
     let path = if PathBuf::from(base.as_ref()).is_absolute() {
         PathBuf::from(base.as_ref())
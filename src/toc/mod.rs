@@ -47,6 +47,32 @@ pub struct Frontmatter<'a> {
     description: Option<Cow<'a, str>>,
     #[builder(default = "true")]
     have_content: bool,
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    author: Option<Cow<'a, str>>,
+    #[builder(default)]
+    #[serde(rename = "createdAt", default, skip_serializing_if = "Option::is_none")]
+    created_at: Option<Cow<'a, str>>,
+    #[builder(default)]
+    #[serde(rename = "updatedAt", default, skip_serializing_if = "Option::is_none")]
+    updated_at: Option<Cow<'a, str>>,
+    #[builder(default)]
+    #[serde(rename = "wordCount", default, skip_serializing_if = "Option::is_none")]
+    word_count: Option<u64>,
+    /// Minutes to read the document
+    #[builder(default)]
+    #[serde(
+        rename = "readingTime",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    reading_time: Option<u64>,
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cover: Option<Cow<'a, str>>,
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source: Option<Cow<'a, str>>,
 }
 
 impl<'a> Frontmatter<'a> {
@@ -0,0 +1,241 @@
+use std::collections::{HashMap, HashSet};
+
+use comrak::nodes::{AstNode, NodeValue};
+use futures_util::future::{ready, FutureExt, LocalBoxFuture};
+use log::debug;
+use regex::Regex;
+
+use super::{anchor_key, Transform, TransformContext};
+use crate::{error::Result, toc::parse::Pinyin};
+
+/// Anchor generated by Yuque before or inside the headings.
+pub const YUQUE_ANCHOR: &str = r#"<a\s+[^>]*?\bname="([^"]*)"[^>]*>\s*(</a>)?"#;
+
+/// Replace the Yuque anchors of the headings with stable slugs, such as `## 标题 {#biao-ti}`,
+/// and rewrite the links to the anchors in the document.
+pub struct AnchorTransform {
+    anchor: Regex,
+}
+
+impl AnchorTransform {
+    pub fn new() -> Self {
+        Self {
+            anchor: Regex::new(&format!("^{}$", YUQUE_ANCHOR)).unwrap(),
+        }
+    }
+
+    /// Ids of the anchors if the html is only made up of them.
+    fn anchor_ids(&self, html: &[u8]) -> Option<Vec<String>> {
+        let html = String::from_utf8_lossy(html);
+
+        let ids = html
+            .split_inclusive("</a>")
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|s| self.anchor.captures(s).map(|caps| caps[1].to_string()))
+            .collect::<Option<Vec<_>>>()?;
+
+        (!ids.is_empty()).then_some(ids)
+    }
+
+    fn html_anchor_ids<'a>(&self, node: &'a AstNode<'a>) -> Option<Vec<String>> {
+        match &node.data.borrow().value {
+            NodeValue::HtmlBlock(block) => self.anchor_ids(&block.literal),
+            NodeValue::HtmlInline(html) => self.anchor_ids(html),
+            _ => None,
+        }
+    }
+
+    fn convert<'a>(&self, node: &'a AstNode<'a>, ctx: &TransformContext) -> Result<()> {
+        // The whole document is handled at once, the links may be before the headings
+        if !matches!(node.data.borrow().value, NodeValue::Document) {
+            return Ok(());
+        }
+
+        let anchors = self.rewrite(node);
+
+        ctx.anchors
+            .lock()
+            .unwrap()
+            .insert(anchor_key(ctx.document), anchors);
+
+        Ok(())
+    }
+
+    /// Rewrite the anchors of the headings and the links to them in the document,
+    /// returns the slugs keyed by the Yuque anchors.
+    pub fn rewrite<'a>(&self, node: &'a AstNode<'a>) -> HashMap<String, String> {
+        let headings = node
+            .descendants()
+            .filter(|n| matches!(n.data.borrow().value, NodeValue::Heading(_)))
+            .collect::<Vec<_>>();
+
+        let mut anchors = HashMap::new();
+        let mut used = HashSet::new();
+
+        for heading in headings {
+            let mut nodes = vec![];
+
+            for n in heading.children() {
+                if let Some(ids) = self.html_anchor_ids(n) {
+                    nodes.push((n, ids));
+                } else if is_closing_tag(n)
+                    && nodes.last().is_some_and(|(prev, _)| {
+                        n.previous_sibling().is_some_and(|p| p.same_node(prev))
+                    })
+                {
+                    // Inline html is split into single tags
+                    nodes.push((n, vec![]));
+                }
+            }
+
+            if let Some(prev) = heading.previous_sibling() {
+                if let Some(ids) = self.html_anchor_ids(prev) {
+                    nodes.push((prev, ids));
+                }
+            }
+
+            if nodes.is_empty() {
+                continue;
+            }
+
+            let slug = unique_slug(&heading_text(heading), &mut used);
+
+            for (n, ids) in nodes.iter() {
+                n.detach();
+                for id in ids {
+                    anchors.insert(id.clone(), slug.clone());
+                }
+            }
+
+            debug!("Anchor of heading: {}", slug);
+
+            // Reuse the anchor node for the custom id, which can not be escaped
+            let (n, _) = nodes.swap_remove(0);
+            n.data.borrow_mut().value =
+                NodeValue::HtmlInline(format!(" {{#{}}}", slug).into_bytes());
+            heading.append(n);
+        }
+
+        for n in node.descendants() {
+            if let NodeValue::Link(link) = &mut n.data.borrow_mut().value {
+                let url = String::from_utf8_lossy(&link.url).to_string();
+
+                if let Some(slug) = url.strip_prefix('#').and_then(|id| anchors.get(id)) {
+                    link.url = format!("#{}", slug).into_bytes();
+                }
+            }
+        }
+
+        anchors
+    }
+}
+
+impl Default for AnchorTransform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transform for AnchorTransform {
+    fn name(&self) -> &str {
+        "anchor"
+    }
+
+    fn transform<'a>(
+        &'a self,
+        node: &'a AstNode<'a>,
+        ctx: &'a TransformContext<'a>,
+    ) -> LocalBoxFuture<'a, Result<()>> {
+        ready(self.convert(node, ctx)).boxed_local()
+    }
+}
+
+fn is_closing_tag<'a>(node: &'a AstNode<'a>) -> bool {
+    matches!(
+        &node.data.borrow().value,
+        NodeValue::HtmlInline(html) if String::from_utf8_lossy(html).trim() == "</a>"
+    )
+}
+
+fn heading_text<'a>(heading: &'a AstNode<'a>) -> String {
+    heading.descendants().fold(String::new(), |mut text, n| {
+        match &n.data.borrow().value {
+            NodeValue::Text(t) => text.push_str(&String::from_utf8_lossy(t)),
+            NodeValue::Code(code) => text.push_str(&String::from_utf8_lossy(&code.literal)),
+            _ => (),
+        }
+        text
+    })
+}
+
+/// Slug of the heading in pinyin, suffixed with a number when it is used.
+fn unique_slug(text: &str, used: &mut HashSet<String>) -> String {
+    let slug = text
+        .trim()
+        .to_pinyin_or_lowercase()
+        .to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+
+    let slug = if slug.is_empty() {
+        "section".to_string()
+    } else {
+        slug
+    };
+
+    let mut unique = slug.clone();
+    let mut i = 1;
+
+    while !used.insert(unique.clone()) {
+        unique = format!("{}-{}", slug, i);
+        i += 1;
+    }
+
+    unique
+}
+
+#[cfg(test)]
+mod tests {
+    use comrak::{format_commonmark, parse_document, Arena, ComrakOptions};
+
+    use super::*;
+
+    #[test]
+    fn slugs() {
+        let mut used = HashSet::new();
+
+        assert_eq!(unique_slug("Hello, World!", &mut used), "hello-world");
+        assert_eq!(unique_slug("hello world", &mut used), "hello-world-1");
+        assert_eq!(unique_slug("hello world", &mut used), "hello-world-2");
+        assert_eq!(unique_slug("  ", &mut used), "section");
+        assert_eq!(unique_slug("!!!", &mut used), "section-1");
+        assert_eq!(unique_slug("标题", &mut used), "biao-ti");
+    }
+
+    #[test]
+    fn rewrite_anchors() {
+        let arena = Arena::new();
+        let options = ComrakOptions::default();
+        let root = parse_document(
+            &arena,
+            "[to intro](#u1a2b)\n\n## <a name=\"u1a2b\"></a>Intro\n\n## Intro\n\n## <a name=\"u3c4d\"></a>Intro\n",
+            &options,
+        );
+
+        let anchors = AnchorTransform::new().rewrite(root);
+
+        assert_eq!(anchors["u1a2b"], "intro");
+        assert_eq!(anchors["u3c4d"], "intro-1");
+
+        let mut output = vec![];
+        format_commonmark(root, &options, &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.contains("[to intro](#intro)"));
+        assert!(output.contains("## Intro {#intro}"));
+        assert!(!output.contains("<a name"));
+    }
+}
@@ -0,0 +1,291 @@
+use std::{ops::Not, path::PathBuf};
+
+use comrak::nodes::{AstNode, NodeValue};
+use futures_util::future::{FutureExt, LocalBoxFuture};
+use log::{debug, info, warn};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+
+use super::{is_domain, Transform, TransformContext};
+use crate::{
+    cache::hash,
+    error::{Error, Result},
+};
+
+/// Characters kept as is in the url of the saved attachments.
+const FILE_NAME: &AsciiSet = &NON_ALPHANUMERIC.remove(b'.').remove(b'-').remove(b'_');
+
+/// Default directory of the attachments, relative to `docs/public`.
+pub const ATTACHMENT_DIR: &str = "assets/files";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AttachmentOptions {
+    /// Directory of the saved attachments, relative to `docs/public`
+    pub dir: String,
+    /// Max size of an attachment in MB, larger ones are kept as remote links
+    pub max_size: u64,
+    /// Allowed extensions of the attachments
+    pub extensions: Vec<String>,
+}
+
+impl Default for AttachmentOptions {
+    fn default() -> Self {
+        Self {
+            dir: ATTACHMENT_DIR.into(),
+            max_size: 50,
+            extensions: [
+                "pdf", "zip", "rar", "7z", "gz", "doc", "docx", "xls", "xlsx", "ppt", "pptx",
+                "txt", "csv", "md",
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+        }
+    }
+}
+
+/// Download the files uploaded to Yuque into the static assets, named by their
+/// original file name, and link to the local copies.
+pub struct AttachmentTransform {
+    options: AttachmentOptions,
+}
+
+impl AttachmentTransform {
+    pub fn new(options: AttachmentOptions) -> Self {
+        Self { options }
+    }
+
+    async fn convert<'a>(&self, node: &'a AstNode<'a>, ctx: &TransformContext<'_>) -> Result<()> {
+        let url = match &node.data.borrow().value {
+            NodeValue::Link(link) => String::from_utf8_lossy(&link.url).to_string(),
+            _ => return Ok(()),
+        };
+
+        let Ok(url) = url::Url::parse(&url) else {
+            return Ok(());
+        };
+
+        if !is_yuque_file(&url, ctx) {
+            return Ok(());
+        }
+
+        let Some(extension) = self.extension(&url) else {
+            return Ok(());
+        };
+
+        let name = file_name(node, &url, &extension);
+
+        let link = self
+            .download(&url, &name, ctx)
+            .await
+            .map_err(|e| Error::Attachment(format!("{}: {}", url, e)))?;
+
+        // Too large, kept as the remote link
+        let Some(link) = link else {
+            return Ok(());
+        };
+
+        if let NodeValue::Link(link_node) = &mut node.data.borrow_mut().value {
+            link_node.url = link.into_bytes();
+        }
+
+        Ok(())
+    }
+
+    /// Extension of the file if it is allowed.
+    fn extension(&self, url: &url::Url) -> Option<String> {
+        let (_, extension) = url.path_segments()?.last()?.rsplit_once('.')?;
+        let extension = extension.to_lowercase();
+
+        self.options
+            .extensions
+            .iter()
+            .any(|e| e.eq_ignore_ascii_case(&extension))
+            .then_some(extension)
+    }
+
+    /// Save the file under `docs/public/{dir}/{hash}/{name}`, return the url of it,
+    /// `None` if the file is larger than `max_size`.
+    async fn download(
+        &self,
+        url: &url::Url,
+        name: &str,
+        ctx: &TransformContext<'_>,
+    ) -> Result<Option<String>> {
+        let dir = self.options.dir.trim_matches('/');
+        let key = &hash(url.as_str())[..16];
+        let link = format!("/{}/{}/{}", dir, key, utf8_percent_encode(name, FILE_NAME));
+
+        let folder = PathBuf::from("docs/public").join(dir).join(key);
+        let path = folder.join(name);
+
+        if path.exists() {
+            debug!("Attachment exists: {}", path.display());
+            return Ok(Some(link));
+        }
+
+        let max_size = self.options.max_size << 20;
+
+        let mut request = ctx.client.get(url.clone());
+
+        // The attachments are only available to the members
+        if url.domain() == Some(ctx.yuque_domain) {
+            request = request.header("X-Auth-Token", ctx.token);
+        }
+
+        let mut response = request.send().await?.error_for_status()?;
+
+        if response.content_length().is_some_and(|len| len > max_size) {
+            self.too_large(url);
+            return Ok(None);
+        }
+
+        let mut bytes = vec![];
+
+        while let Some(chunk) = response.chunk().await? {
+            bytes.extend_from_slice(&chunk);
+
+            if bytes.len() as u64 > max_size {
+                self.too_large(url);
+                return Ok(None);
+            }
+        }
+
+        if folder.exists().not() {
+            tokio::fs::create_dir_all(&folder).await?;
+        }
+
+        tokio::fs::write(&path, &bytes).await?;
+
+        info!("Save attachment to: {}", path.display());
+
+        Ok(Some(link))
+    }
+
+    fn too_large(&self, url: &url::Url) {
+        warn!(
+            "Attachment larger than {}MB, keep the remote link: {}",
+            self.options.max_size, url
+        );
+    }
+}
+
+impl Transform for AttachmentTransform {
+    fn name(&self) -> &str {
+        "attachment"
+    }
+
+    fn transform<'a>(
+        &'a self,
+        node: &'a AstNode<'a>,
+        ctx: &'a TransformContext<'a>,
+    ) -> LocalBoxFuture<'a, Result<()>> {
+        self.convert(node, ctx).boxed_local()
+    }
+}
+
+/// Files uploaded to Yuque, on the Yuque site or its cdn.
+fn is_yuque_file(url: &url::Url, ctx: &TransformContext) -> bool {
+    let Some(domain) = url.domain() else {
+        return false;
+    };
+
+    let is_yuque = domain == ctx.yuque_domain || is_domain(domain, "yuque.com");
+
+    (is_yuque && url.path().starts_with("/attachments/")) || is_domain(domain, "nlark.com")
+}
+
+/// The text of the link is the original file name, the name in the url is generated by Yuque.
+fn file_name<'a>(node: &'a AstNode<'a>, url: &url::Url, extension: &str) -> String {
+    let text = node
+        .descendants()
+        .skip(1)
+        .fold(String::new(), |mut text, n| {
+            if let NodeValue::Text(t) = &n.data.borrow().value {
+                text.push_str(&String::from_utf8_lossy(t));
+            }
+            text
+        });
+
+    let name = if text.to_lowercase().ends_with(&format!(".{}", extension)) {
+        text
+    } else {
+        url.path_segments()
+            .and_then(|mut segments| segments.next_back().map(String::from))
+            .unwrap_or_else(|| format!("file.{}", extension))
+    };
+
+    // Keep the name inside the directory
+    name.replace(['/', '\\'], "_")
+        .trim_start_matches('.')
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use comrak::{parse_document, Arena, ComrakOptions};
+
+    use super::*;
+    use crate::transform::{serve, LinkTransform, TestContext};
+
+    #[test]
+    fn extensions() {
+        let transform = AttachmentTransform::new(AttachmentOptions::default());
+        let extension = |url: &str| transform.extension(&url::Url::parse(url).unwrap());
+
+        assert_eq!(
+            extension("https://www.yuque.com/attachments/a/Report.PDF"),
+            Some("pdf".into())
+        );
+        assert_eq!(
+            extension("https://www.yuque.com/attachments/a/setup.exe"),
+            None
+        );
+        assert_eq!(extension("https://www.yuque.com/attachments/a/noext"), None);
+    }
+
+    #[tokio::test]
+    async fn too_large() {
+        let transform = AttachmentTransform::new(AttachmentOptions {
+            dir: "assets/too-large-test".into(),
+            max_size: 0,
+            ..Default::default()
+        });
+        let context = TestContext::new(Default::default());
+        let ctx = context.context("user/repo");
+
+        let url = serve(b"larger than zero".to_vec(), "/attachments/large.pdf").await;
+        let link = transform.download(&url, "large.pdf", &ctx).await.unwrap();
+
+        assert_eq!(link, None);
+        assert!(!PathBuf::from("docs/public/assets/too-large-test").exists());
+    }
+
+    #[tokio::test]
+    async fn remote_attachment_links() {
+        let url = "https://www.yuque.com/attachments/yuque/0/2023/exe/1/setup.exe";
+        let arena = Arena::new();
+        let root = parse_document(
+            &arena,
+            &format!("[setup.exe]({})", url),
+            &ComrakOptions::default(),
+        );
+        let link = root.first_child().unwrap().first_child().unwrap();
+
+        let context = TestContext::new([("user/repo".to_string(), Default::default())].into());
+        let ctx = context.context("user/repo");
+
+        // Kept as the remote link, which is not a broken document link
+        AttachmentTransform::new(AttachmentOptions::default())
+            .transform(link, &ctx)
+            .await
+            .unwrap();
+        LinkTransform.transform(link, &ctx).await.unwrap();
+
+        match &link.data.borrow().value {
+            NodeValue::Link(link) => assert_eq!(link.url, url.as_bytes()),
+            _ => unreachable!(),
+        }
+    }
+}
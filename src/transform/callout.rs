@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+
+use comrak::nodes::{AstNode, NodeValue};
+use futures_util::future::{ready, FutureExt, LocalBoxFuture};
+use log::debug;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use super::{Transform, TransformContext};
+use crate::error::Result;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct CalloutOptions {
+    /// Yuque callout kind to the container of the theme, merged into the default mapping
+    pub mapping: HashMap<String, String>,
+}
+
+/// Convert the Yuque callouts such as `:::tips` into the containers of the theme.
+pub struct CalloutTransform {
+    mapping: HashMap<String, String>,
+    pattern: Regex,
+}
+
+impl CalloutTransform {
+    pub fn new(options: CalloutOptions) -> Self {
+        let mut mapping = [
+            ("tips", "tip"),
+            ("info", "info"),
+            ("success", "tip"),
+            ("warning", "warning"),
+            ("danger", "danger"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect::<HashMap<_, _>>();
+
+        mapping.extend(
+            options
+                .mapping
+                .into_iter()
+                .map(|(k, v)| (k.to_lowercase(), v)),
+        );
+
+        Self {
+            mapping,
+            pattern: Regex::new(r"^:::\s*([\w-]+)(.*)$").unwrap(),
+        }
+    }
+
+    fn convert<'a>(&self, node: &'a AstNode<'a>) -> Result<()> {
+        // Only the first line of a paragraph opens a callout
+        let is_first_line = node.previous_sibling().is_none()
+            && node
+                .parent()
+                .is_some_and(|p| matches!(p.data.borrow().value, NodeValue::Paragraph));
+
+        if !is_first_line {
+            return Ok(());
+        }
+
+        let mut data = node.data.borrow_mut();
+
+        let NodeValue::Text(text) = &mut data.value else {
+            return Ok(());
+        };
+
+        let line = String::from_utf8_lossy(text).to_string();
+
+        let Some(caps) = self.pattern.captures(line.trim()) else {
+            return Ok(());
+        };
+
+        let Some(container) = self.mapping.get(&caps[1].to_lowercase()) else {
+            return Ok(());
+        };
+
+        debug!("Convert callout `{}` to `{}`.", &caps[1], container);
+
+        *text = format!("::: {}{}", container, &caps[2]).into_bytes();
+
+        Ok(())
+    }
+}
+
+impl Transform for CalloutTransform {
+    fn name(&self) -> &str {
+        "callout"
+    }
+
+    fn transform<'a>(
+        &'a self,
+        node: &'a AstNode<'a>,
+        _: &'a TransformContext<'a>,
+    ) -> LocalBoxFuture<'a, Result<()>> {
+        ready(self.convert(node)).boxed_local()
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+
+use base64::Engine;
+use comrak::nodes::{AstNode, NodeCodeBlock, NodeValue};
+use futures_util::future::{ready, FutureExt, LocalBoxFuture};
+use log::debug;
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{card_params, Transform, TransformContext};
+use crate::error::Result;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct DiagramOptions {
+    /// Yuque diagram type to the language of the code block, merged into the default mapping
+    pub languages: HashMap<String, String>,
+}
+
+/// Replace the text diagrams rendered by Yuque with fenced code blocks of their source,
+/// the diagrams without source such as the boards are kept as images.
+pub struct DiagramTransform {
+    languages: HashMap<String, String>,
+}
+
+impl DiagramTransform {
+    pub fn new(options: DiagramOptions) -> Self {
+        let mut languages = [
+            ("puml", "plantuml"),
+            ("plantuml", "plantuml"),
+            ("mermaid", "mermaid"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect::<HashMap<_, _>>();
+
+        languages.extend(
+            options
+                .languages
+                .into_iter()
+                .map(|(k, v)| (k.to_lowercase(), v)),
+        );
+
+        Self { languages }
+    }
+
+    fn convert<'a>(&self, node: &'a AstNode<'a>) -> Result<()> {
+        let url = match &node.data.borrow().value {
+            NodeValue::Image(image) => String::from_utf8_lossy(&image.url).to_string(),
+            _ => return Ok(()),
+        };
+
+        let Some((kind, code)) = diagram_source(&url) else {
+            return Ok(());
+        };
+
+        let Some(language) = self.languages.get(&kind.to_lowercase()) else {
+            return Ok(());
+        };
+
+        // Only the diagram which is a paragraph itself is replaced by the paragraph,
+        // the diagrams inside the text are kept as images
+        let Some(parent) = node.parent().filter(|p| {
+            matches!(p.data.borrow().value, NodeValue::Paragraph)
+                && node.previous_sibling().is_none()
+                && node.next_sibling().is_none()
+        }) else {
+            return Ok(());
+        };
+
+        debug!("Convert {} diagram to `{}` code block.", kind, language);
+
+        let mut literal = code.trim_end().to_string();
+        literal.push('\n');
+
+        node.detach();
+        parent.data.borrow_mut().value = NodeValue::CodeBlock(NodeCodeBlock {
+            fenced: true,
+            fence_char: b'`',
+            fence_length: fence_length(&literal),
+            fence_offset: 0,
+            info: language.clone().into_bytes(),
+            literal: literal.into_bytes(),
+        });
+
+        Ok(())
+    }
+}
+
+impl Transform for DiagramTransform {
+    fn name(&self) -> &str {
+        "diagram"
+    }
+
+    fn transform<'a>(
+        &'a self,
+        node: &'a AstNode<'a>,
+        _: &'a TransformContext<'a>,
+    ) -> LocalBoxFuture<'a, Result<()>> {
+        ready(self.convert(node)).boxed_local()
+    }
+}
+
+/// The fence is longer than any run of backticks in the source.
+fn fence_length(literal: &str) -> usize {
+    let longest = literal
+        .split(|c: char| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or_default();
+
+    (longest + 1).max(3)
+}
+
+/// Type and source of the diagram, from `#card=diagram&type=...&code=...`
+/// or `#lake_card_v2=...`, which is the base64 of the url encoded card json.
+fn diagram_source(url: &str) -> Option<(String, String)> {
+    let (_, fragment) = url.split_once('#')?;
+
+    if let Some(card) = fragment
+        .split('&')
+        .find_map(|param| param.strip_prefix("lake_card_v2="))
+    {
+        let card = percent_decode_str(card).decode_utf8_lossy();
+        let card = base64::prelude::BASE64_STANDARD
+            .decode(card.as_bytes())
+            .ok()?;
+        let card = String::from_utf8_lossy(&card);
+        let card = percent_decode_str(&card).decode_utf8_lossy();
+        let card = serde_json::from_str::<Value>(&card).ok()?;
+
+        return Some((
+            card["type"].as_str()?.to_string(),
+            card["code"].as_str()?.to_string(),
+        ));
+    }
+
+    let mut params = card_params(url)?;
+
+    if params.get("card").map(String::as_str) != Some("diagram") {
+        return None;
+    }
+
+    Some((params.remove("type")?, params.remove("code")?))
+}
+
+#[cfg(test)]
+mod tests {
+    use comrak::{format_commonmark, parse_document, Arena, ComrakOptions};
+
+    use super::*;
+
+    fn image(code: &str) -> String {
+        let card = url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("card", "diagram")
+            .append_pair("type", "mermaid")
+            .append_pair("code", code)
+            .finish();
+
+        format!("![](https://cdn.nlark.com/yuque/diagram.svg#{})", card)
+    }
+
+    /// Convert the diagrams, then parse the output again.
+    fn convert<'a>(arena: &'a Arena<AstNode<'a>>, markdown: &str) -> &'a AstNode<'a> {
+        let options = ComrakOptions::default();
+        let root = parse_document(arena, markdown, &options);
+        let transform = DiagramTransform::new(DiagramOptions::default());
+
+        for node in root.descendants().collect::<Vec<_>>() {
+            transform.convert(node).unwrap();
+        }
+
+        let mut output = vec![];
+        format_commonmark(root, &options, &mut output).unwrap();
+
+        parse_document(arena, &String::from_utf8(output).unwrap(), &options)
+    }
+
+    fn code_blocks<'a>(root: &'a AstNode<'a>) -> Vec<(String, String)> {
+        root.descendants()
+            .filter_map(|node| match &node.data.borrow().value {
+                NodeValue::CodeBlock(block) => Some((
+                    String::from_utf8_lossy(&block.info).to_string(),
+                    String::from_utf8_lossy(&block.literal).to_string(),
+                )),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn backtick_fence() {
+        let code = "graph TD\n```\n<b>A</b>-->B";
+        let arena = Arena::new();
+        let root = convert(&arena, &image(code));
+
+        assert_eq!(
+            code_blocks(root),
+            vec![("mermaid".to_string(), format!("{}\n", code))]
+        );
+        assert!(root
+            .descendants()
+            .all(|node| !matches!(node.data.borrow().value, NodeValue::HtmlInline(_))));
+    }
+
+    #[test]
+    fn diagram_in_list() {
+        let code = "graph TD\nA-->B";
+        let arena = Arena::new();
+        let root = convert(&arena, &format!("- {}\n- next\n", image(code)));
+
+        assert_eq!(
+            code_blocks(root),
+            vec![("mermaid".to_string(), format!("{}\n", code))]
+        );
+
+        let list = root.first_child().unwrap();
+        assert!(matches!(list.data.borrow().value, NodeValue::List(_)));
+        assert_eq!(list.children().count(), 2);
+    }
+
+    #[test]
+    fn diagram_in_text() {
+        let arena = Arena::new();
+        let root = convert(&arena, &format!("see {}", image("graph TD\nA-->B")));
+
+        assert!(code_blocks(root).is_empty());
+        assert!(root
+            .descendants()
+            .any(|node| matches!(node.data.borrow().value, NodeValue::Image(_))));
+    }
+}
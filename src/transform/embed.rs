@@ -0,0 +1,56 @@
+use std::sync::Arc;
+
+use comrak::nodes::{AstNode, NodeHtmlBlock, NodeValue};
+use futures_util::future::{ready, FutureExt, LocalBoxFuture};
+use log::info;
+
+use super::{Transform, TransformContext};
+use crate::{embed::EmbedRegistry, error::Result};
+
+/// Replace the links of the embed providers with their iframes.
+pub struct EmbedTransform {
+    embeds: Arc<EmbedRegistry>,
+}
+
+impl EmbedTransform {
+    pub fn new(embeds: Arc<EmbedRegistry>) -> Self {
+        Self { embeds }
+    }
+
+    fn embed<'a>(&self, node: &'a AstNode<'a>) -> Result<()> {
+        let url = match &node.data.borrow().value {
+            NodeValue::Link(link) => String::from_utf8_lossy(&link.url).to_string(),
+            _ => return Ok(()),
+        };
+
+        let Some((provider, literal)) = self.embeds.embed(&url) else {
+            return Ok(());
+        };
+
+        info!("Embed {} link: {}", provider, url);
+        let literal = literal.into_bytes();
+        node.children().for_each(|node| node.detach());
+
+        let mut data = node.data.borrow_mut();
+        data.value = NodeValue::HtmlBlock(NodeHtmlBlock {
+            block_type: 6,
+            literal,
+        });
+
+        Ok(())
+    }
+}
+
+impl Transform for EmbedTransform {
+    fn name(&self) -> &str {
+        "embed"
+    }
+
+    fn transform<'a>(
+        &'a self,
+        node: &'a AstNode<'a>,
+        _: &'a TransformContext<'a>,
+    ) -> LocalBoxFuture<'a, Result<()>> {
+        ready(self.embed(node)).boxed_local()
+    }
+}
@@ -0,0 +1,201 @@
+use comrak::nodes::{AstNode, NodeHtmlBlock, NodeValue};
+use futures_util::future::{FutureExt, LocalBoxFuture};
+use log::info;
+
+use super::{Transform, TransformContext};
+use crate::{
+    config::{ImageConfig, ImageMode},
+    error::{Error, Result},
+    images::{data_uri, is_svg, mime_type, process_image, save_asset},
+    sanitize::{escape_attribute, sanitize_svg},
+};
+
+/// Download the remote images, then save them as assets or inline them.
+pub struct ImageTransform {
+    config: ImageConfig,
+}
+
+impl ImageTransform {
+    pub fn new(config: ImageConfig) -> Self {
+        Self { config }
+    }
+
+    async fn convert<'a>(&self, node: &'a AstNode<'a>, ctx: &TransformContext<'_>) -> Result<()> {
+        let url = match &node.data.borrow().value {
+            NodeValue::Image(i) => String::from_utf8_lossy(&i.url).to_string(),
+            _ => return Ok(()),
+        };
+
+        // Local images and data uri are kept as is
+        let url = match url::Url::parse(&url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => url,
+            Ok(_) | Err(url::ParseError::RelativeUrlWithoutBase) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+
+        info!("Find image url: {}", url);
+
+        let cached = ctx.image_cache.lock().unwrap().get(url.as_str());
+
+        let bytes = match cached {
+            Some(bytes) => bytes,
+            None => {
+                let response = ctx
+                    .client
+                    .get(url.clone())
+                    .send()
+                    .await?
+                    .error_for_status()?;
+
+                let content_type = response
+                    .headers()
+                    .get(reqwest::header::CONTENT_TYPE)
+                    .and_then(|v| v.to_str().ok())
+                    .map(String::from);
+
+                let bytes = response.bytes().await?.to_vec();
+
+                ctx.image_cache
+                    .lock()
+                    .unwrap()
+                    .insert(url.as_str(), &bytes, content_type)?;
+
+                bytes
+            }
+        };
+
+        if is_svg(&bytes) {
+            // Svg in `<img>` can not run scripts, fallback to it when the svg is unsafe
+            let src = match (
+                self.config.mode,
+                sanitize_svg(&String::from_utf8_lossy(&bytes)),
+            ) {
+                (ImageMode::Local, Some(svg)) => {
+                    save_asset(svg.as_bytes(), "svg", &self.config.assets)?
+                }
+                (ImageMode::Base64, Some(svg)) => {
+                    node.data.borrow_mut().value = NodeValue::HtmlBlock(NodeHtmlBlock {
+                        block_type: 7,
+                        literal: svg.into_bytes(),
+                    });
+                    return Ok(());
+                }
+                (_, None) => data_uri(&bytes, mime_type("svg")),
+            };
+
+            if let NodeValue::Image(i) = &mut node.data.borrow_mut().value {
+                i.url = src.into_bytes();
+            }
+
+            return Ok(());
+        }
+
+        // Decoding and encoding are too heavy to run on the runtime
+        let config = self.config.clone();
+        let image = tokio::task::spawn_blocking(move || process_image(&bytes, &config))
+            .await
+            .map_err(|e| Error::Internal(e.to_string()))??;
+
+        // The theme does not prefix the raw html with `base`
+        let src = match self.config.mode {
+            ImageMode::Local => format!(
+                "{}{}",
+                ctx.base.trim_end_matches('/'),
+                save_asset(&image.bytes, image.extension(), &self.config.assets)?
+            ),
+            ImageMode::Base64 => image.to_data_uri(),
+        };
+
+        let alt = node
+            .descendants()
+            .skip(1)
+            .fold(String::new(), |mut alt, n| {
+                if let NodeValue::Text(text) = &n.data.borrow().value {
+                    alt.push_str(&String::from_utf8_lossy(text));
+                }
+                alt
+            });
+
+        node.children().for_each(|node| node.detach());
+
+        // Keep the size of the image so the theme can reserve the space before loaded
+        node.data.borrow_mut().value = NodeValue::HtmlInline(
+            format!(
+                r#"<img src="{}" alt="{}" width="{}" height="{}" loading="lazy" />"#,
+                escape_attribute(&src),
+                escape_attribute(&alt),
+                image.width,
+                image.height
+            )
+            .into_bytes(),
+        );
+
+        Ok(())
+    }
+}
+
+impl Transform for ImageTransform {
+    fn name(&self) -> &str {
+        "convert_image"
+    }
+
+    fn transform<'a>(
+        &'a self,
+        node: &'a AstNode<'a>,
+        ctx: &'a TransformContext<'a>,
+    ) -> LocalBoxFuture<'a, Result<()>> {
+        self.convert(node, ctx).boxed_local()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Cursor};
+
+    use comrak::{parse_document, Arena, ComrakOptions};
+    use image::{ImageOutputFormat, RgbImage};
+
+    use super::*;
+    use crate::transform::{serve, TestContext};
+
+    #[tokio::test]
+    async fn local_image_with_base() {
+        let mut png = vec![];
+        RgbImage::new(2, 1)
+            .write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+            .unwrap();
+        let url = serve(png, "/image.png").await;
+
+        let arena = Arena::new();
+        let root = parse_document(
+            &arena,
+            &format!("![alt]({})", url),
+            &ComrakOptions::default(),
+        );
+        let image = root.first_child().unwrap().first_child().unwrap();
+
+        let mut context = TestContext::new(Default::default());
+        context.base = "/blog/".into();
+
+        let transform = ImageTransform::new(ImageConfig {
+            assets: "image-base-test".into(),
+            ..Default::default()
+        });
+        transform
+            .convert(image, &context.context("user/repo"))
+            .await
+            .unwrap();
+
+        let html = match &image.data.borrow().value {
+            NodeValue::HtmlInline(html) => String::from_utf8_lossy(html).to_string(),
+            _ => unreachable!(),
+        };
+
+        fs::remove_dir_all("docs/public/image-base-test").unwrap();
+        fs::remove_dir("docs/public").ok();
+        fs::remove_dir("docs").ok();
+
+        assert!(html.starts_with(r#"<img src="/blog/image-base-test/"#));
+        assert!(html.contains(r#"width="2" height="1""#));
+    }
+}
@@ -0,0 +1,199 @@
+use comrak::nodes::{AstNode, NodeLink, NodeValue};
+use futures_util::future::{ready, FutureExt, LocalBoxFuture};
+use log::info;
+
+use super::{anchor_key, is_domain, Transform, TransformContext};
+use crate::error::{Error, Result};
+
+/// Rewrite the links to Yuque documents into the links to the generated pages.
+pub struct LinkTransform;
+
+impl Transform for LinkTransform {
+    fn name(&self) -> &str {
+        "convert_link"
+    }
+
+    fn transform<'a>(
+        &'a self,
+        node: &'a AstNode<'a>,
+        ctx: &'a TransformContext<'a>,
+    ) -> LocalBoxFuture<'a, Result<()>> {
+        ready(convert_link(node, ctx)).boxed_local()
+    }
+}
+
+fn convert_link<'a>(node: &'a AstNode<'a>, ctx: &TransformContext) -> Result<()> {
+    let (url, content) = match &node.data.borrow().value {
+        NodeValue::Link(link) => (
+            String::from_utf8_lossy(&link.url).to_string(),
+            node.first_child(),
+        ),
+        _ => return Ok(()),
+    };
+
+    // Relative links and in-page anchors are kept as is
+    let origin_url = match url::Url::parse(&url) {
+        Ok(url) => url,
+        Err(url::ParseError::RelativeUrlWithoutBase) => return Ok(()),
+        Err(e) => return Err(Error::InvalidUrl(format!("{}: {}", e, url))),
+    };
+
+    if document(&origin_url, ctx).is_none() {
+        return Ok(());
+    }
+
+    let local = local_url(&origin_url, ctx);
+
+    ctx.links
+        .lock()
+        .unwrap()
+        .insert(url, local.as_ref().cloned().unwrap_or_default());
+
+    let url = local?;
+
+    info!("change url to inner link: {}", url);
+    node.children().for_each(|node| node.detach());
+
+    let mut data = node.data.borrow_mut();
+    data.value = NodeValue::Link(NodeLink {
+        url: url.into_bytes(),
+        title: vec![],
+    });
+
+    if let Some(content) = content {
+        node.append(content);
+    }
+
+    Ok(())
+}
+
+/// Url of the generated page the Yuque link points to.
+pub fn local_url(origin_url: &url::Url, ctx: &TransformContext) -> Result<String> {
+    let path = document(origin_url, ctx)
+        .and_then(|(namespace, slug)| ctx.article_path.get(&namespace)?.get(slug))
+        .ok_or_else(|| Error::BrokenLink(origin_url.to_string()))?;
+
+    // The anchor may not be known when the document is not generated yet
+    let fragment = origin_url.fragment().map(|id| {
+        ctx.anchors
+            .lock()
+            .unwrap()
+            .get(&anchor_key(&path.display().to_string()))
+            .and_then(|anchors| anchors.get(id).cloned())
+            .unwrap_or_else(|| id.to_string())
+    });
+
+    let path = path.strip_prefix("./docs").unwrap().display();
+
+    Ok(match fragment {
+        Some(fragment) => format!("/{}#{}", path, fragment),
+        None => format!("/{}", path),
+    })
+}
+
+fn is_yuque(url: &url::Url, ctx: &TransformContext) -> bool {
+    url.domain()
+        .is_some_and(|domain| domain == ctx.yuque_domain || is_domain(domain, "yuque.com"))
+}
+
+/// Namespace and slug of the document the url points to, which is `/{user}/{repo}/{slug}`
+/// of a configured namespace. The other Yuque pages, such as the repos, the users and
+/// the attachments, are not generated and kept as is.
+fn document<'u>(url: &'u url::Url, ctx: &TransformContext) -> Option<(String, &'u str)> {
+    if !is_yuque(url, ctx) {
+        return None;
+    }
+
+    let segments = url
+        .path_segments()?
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>();
+
+    let [user, repo, slug] = segments[..] else {
+        return None;
+    };
+
+    let namespace = format!("{}/{}", user, repo);
+
+    ctx.article_path
+        .contains_key(&namespace)
+        .then_some((namespace, slug))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use comrak::{parse_document, Arena, ComrakOptions};
+
+    use super::*;
+    use crate::transform::TestContext;
+
+    fn context() -> TestContext {
+        TestContext::new(
+            [(
+                "user/repo".to_string(),
+                [("intro".to_string(), PathBuf::from("./docs/repo/intro.md"))].into(),
+            )]
+            .into(),
+        )
+    }
+
+    /// Url of the converted link.
+    fn convert(url: &str, ctx: &TransformContext) -> Result<String> {
+        let arena = Arena::new();
+        let root = parse_document(
+            &arena,
+            &format!("[link]({})", url),
+            &ComrakOptions::default(),
+        );
+        let link = root.first_child().unwrap().first_child().unwrap();
+
+        convert_link(link, ctx)?;
+
+        let url = match &link.data.borrow().value {
+            NodeValue::Link(link) => String::from_utf8_lossy(&link.url).to_string(),
+            _ => unreachable!(),
+        };
+
+        Ok(url)
+    }
+
+    #[test]
+    fn documents() {
+        let context = context();
+        let ctx = context.context("user/repo");
+
+        assert_eq!(
+            convert("https://www.yuque.com/user/repo/intro", &ctx).unwrap(),
+            "/repo/intro.md"
+        );
+        assert_eq!(
+            convert("https://www.yuque.com/user/repo/intro#abc", &ctx).unwrap(),
+            "/repo/intro.md#abc"
+        );
+        assert!(matches!(
+            convert("https://www.yuque.com/user/repo/missing", &ctx),
+            Err(Error::BrokenLink(_))
+        ));
+    }
+
+    #[test]
+    fn other_yuque_pages() {
+        let context = context();
+        let ctx = context.context("user/repo");
+
+        for url in [
+            "https://www.yuque.com/user",
+            "https://www.yuque.com/user/repo",
+            "https://www.yuque.com/user/other/intro",
+            "https://www.yuque.com/attachments/yuque/0/2023/pdf/1/a.pdf",
+            "https://www.yuque.com/intro",
+            "https://notyuque.com/user/repo/intro",
+        ] {
+            assert_eq!(convert(url, &ctx).unwrap(), url);
+        }
+
+        assert!(ctx.links.lock().unwrap().is_empty());
+    }
+}
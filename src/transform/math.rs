@@ -0,0 +1,144 @@
+use comrak::nodes::{AstNode, NodeValue};
+use futures_util::future::{ready, FutureExt, LocalBoxFuture};
+use log::debug;
+
+use super::{card_params, Transform, TransformContext};
+use crate::error::Result;
+
+/// Replace the formula images rendered by Yuque with their LaTeX source,
+/// `$$...$$` when the formula is a paragraph itself, otherwise `$...$`.
+pub struct MathTransform;
+
+impl MathTransform {
+    fn convert<'a>(&self, node: &'a AstNode<'a>) -> Result<()> {
+        let url = match &node.data.borrow().value {
+            NodeValue::Image(image) => String::from_utf8_lossy(&image.url).to_string(),
+            _ => return Ok(()),
+        };
+
+        let Some(params) = card_params(&url) else {
+            return Ok(());
+        };
+
+        if params.get("card").map(String::as_str) != Some("math") {
+            return Ok(());
+        }
+
+        let Some(code) = params.get("code").map(|code| code.trim()) else {
+            return Ok(());
+        };
+
+        let is_block = node.previous_sibling().is_none()
+            && node.next_sibling().is_none()
+            && node
+                .parent()
+                .is_some_and(|p| matches!(p.data.borrow().value, NodeValue::Paragraph));
+
+        debug!("Convert formula: {}", code);
+
+        // Raw output, the LaTeX must not be escaped as markdown
+        let math = if is_block {
+            format!("$$\n{}\n$$", latex(code).join("\n"))
+        } else {
+            format!("${}$", latex(code).join(" "))
+        };
+
+        node.children().for_each(|node| node.detach());
+        node.data.borrow_mut().value = NodeValue::HtmlInline(math.into_bytes());
+
+        Ok(())
+    }
+}
+
+/// Lines of the LaTeX which are safe to be written as raw html.
+///
+/// The output is not sanitized anymore, so `<` and `>` are written as TeX commands instead of tags.
+/// Blank lines would end the paragraph of the formula, so they are dropped.
+fn latex(code: &str) -> Vec<String> {
+    code.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.replace('<', "\\lt ").replace('>', "\\gt "))
+        .collect()
+}
+
+impl Transform for MathTransform {
+    fn name(&self) -> &str {
+        "math"
+    }
+
+    fn transform<'a>(
+        &'a self,
+        node: &'a AstNode<'a>,
+        _: &'a TransformContext<'a>,
+    ) -> LocalBoxFuture<'a, Result<()>> {
+        ready(self.convert(node)).boxed_local()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use comrak::{format_commonmark, parse_document, Arena, ComrakOptions};
+
+    use super::*;
+    use crate::convert::lake_to_markdown;
+
+    fn convert(markdown: &str) -> String {
+        let arena = Arena::new();
+        let options = ComrakOptions::default();
+        let root = parse_document(&arena, markdown, &options);
+
+        for node in root.descendants().collect::<Vec<_>>() {
+            MathTransform.convert(node).unwrap();
+        }
+
+        let mut output = vec![];
+        format_commonmark(root, &options, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn html_in_formula() {
+        let output = convert(
+            "![](https://cdn.nlark.com/x.svg#card=math&code=a%3Cb%20%3Cimg%20src%3Dx%20onerror%3Dalert(1)%3E)",
+        );
+
+        assert_eq!(
+            output,
+            "$$\na\\lt b \\lt img src=x onerror=alert(1)\\gt \n$$\n"
+        );
+    }
+
+    #[test]
+    fn blank_lines_in_formula() {
+        let code = "a%20%3D%201%0A%0A%5C%5C%20b%20%3D%202";
+
+        assert_eq!(
+            convert(&format!(
+                "![](https://cdn.nlark.com/x.svg#card=math&code={})",
+                code
+            )),
+            "$$\na = 1\n\\\\ b = 2\n$$\n"
+        );
+        assert_eq!(
+            convert(&format!(
+                "x ![](https://cdn.nlark.com/x.svg#card=math&code={})",
+                code
+            )),
+            "x $a = 1 \\\\ b = 2$\n"
+        );
+    }
+
+    #[test]
+    fn lake_math_cards() {
+        let value = "data:%7B%22code%22%3A%22a_1%20*%20b_2%22%7D";
+        let markdown = lake_to_markdown(&format!(
+            r#"<!doctype lake><card name="math" value="{0}"></card><p>inline <card name="math" value="{0}"></card></p>"#,
+            value
+        ));
+
+        assert_eq!(
+            convert(&markdown),
+            "$$\na_1 * b_2\n$$\n\ninline $a_1 * b_2$\n"
+        );
+    }
+}
@@ -0,0 +1,334 @@
+//! 对文档 AST 的转换，每个知识库可以在 `config.yml` 中启用、禁用、排序和配置
+
+mod anchor;
+mod attachment;
+mod callout;
+mod diagram;
+mod embed;
+mod image;
+mod link;
+mod math;
+mod sanitize;
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use comrak::nodes::AstNode;
+use futures_util::future::LocalBoxFuture;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    config::{Check, ImageConfig, SanitizeConfig, TransformConfig},
+    embed::EmbedRegistry,
+    error::{Error, Result},
+    images::ImageCache,
+};
+
+pub use self::{
+    anchor::{AnchorTransform, YUQUE_ANCHOR},
+    attachment::{AttachmentOptions, AttachmentTransform, ATTACHMENT_DIR},
+    callout::{CalloutOptions, CalloutTransform},
+    diagram::{DiagramOptions, DiagramTransform},
+    embed::EmbedTransform,
+    image::ImageTransform,
+    link::{local_url, LinkTransform},
+    math::MathTransform,
+    sanitize::SanitizeTransform,
+};
+
+/// Transforms used when the namespace does not configure any, in order.
+/// `sanitize_html` should run first, the others produce trusted html.
+pub const DEFAULT_TRANSFORMS: &[&str] = &[
+    "sanitize_html",
+    "callout",
+    "anchor",
+    "math",
+    "diagram",
+    "convert_image",
+    "attachment",
+    "embed",
+    "convert_link",
+];
+
+pub trait Transform: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Called on each node of the document, the nodes are transformed concurrently.
+    /// Sync transforms can return a ready future.
+    fn transform<'a>(
+        &'a self,
+        node: &'a AstNode<'a>,
+        ctx: &'a TransformContext<'a>,
+    ) -> LocalBoxFuture<'a, Result<()>>;
+}
+
+/// The document being transformed.
+pub struct TransformContext<'c> {
+    pub namespace: &'c str,
+    pub document: &'c str,
+    /// Path of the documents keyed by namespace and slug
+    pub article_path: &'c HashMap<String, HashMap<String, PathBuf>>,
+    pub yuque_domain: &'c str,
+    /// Token of the Yuque api, only sent to Yuque
+    pub token: &'c str,
+    /// `base` of the site, the local urls in the raw html are not rewritten by the theme
+    pub base: &'c str,
+    /// Shared client of the generator
+    pub client: &'c reqwest::Client,
+    pub image_cache: &'c Mutex<ImageCache>,
+    /// Slugs of the anchors keyed by the Yuque anchor, of each document
+    pub anchors: &'c Mutex<HashMap<String, HashMap<String, String>>>,
+    /// Yuque links resolved in the document, which the cached output depends on
+    pub links: &'c Mutex<HashMap<String, String>>,
+}
+
+/// Owner of the borrowed state of [`TransformContext`] in the tests.
+#[cfg(test)]
+pub struct TestContext {
+    pub base: String,
+    article_path: HashMap<String, HashMap<String, PathBuf>>,
+    client: reqwest::Client,
+    image_cache: Mutex<ImageCache>,
+    anchors: Mutex<HashMap<String, HashMap<String, String>>>,
+    links: Mutex<HashMap<String, String>>,
+}
+
+#[cfg(test)]
+impl TestContext {
+    pub fn new(article_path: HashMap<String, HashMap<String, PathBuf>>) -> Self {
+        Self {
+            base: "/".into(),
+            article_path,
+            client: reqwest::Client::builder().no_proxy().build().unwrap(),
+            // Nothing is kept in the cache
+            image_cache: Mutex::new(ImageCache::load(
+                std::env::temp_dir().join("yuque-ssg-test-images"),
+                0,
+            )),
+            anchors: Default::default(),
+            links: Default::default(),
+        }
+    }
+
+    pub fn context<'c>(&'c self, namespace: &'c str) -> TransformContext<'c> {
+        TransformContext {
+            namespace,
+            document: "./docs/test.md",
+            article_path: &self.article_path,
+            yuque_domain: "www.yuque.com",
+            token: "",
+            base: &self.base,
+            client: &self.client,
+            image_cache: &self.image_cache,
+            anchors: &self.anchors,
+            links: &self.links,
+        }
+    }
+}
+
+/// Serve one response of the body on a local port, return the url of `path` on it.
+#[cfg(test)]
+pub async fn serve(body: Vec<u8>, path: &str) -> url::Url {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = [0; 1024];
+        let _ = stream.read(&mut request).await.unwrap();
+
+        let head = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", body.len());
+        stream.write_all(head.as_bytes()).await.unwrap();
+        stream.write_all(&body).await.unwrap();
+    });
+
+    url::Url::parse(&format!("http://{}{}", address, path)).unwrap()
+}
+
+/// Parameters of the cards rendered as images by Yuque, which are
+/// in the fragment of the url, such as `#card=math&code=...`.
+pub fn card_params(url: &str) -> Option<HashMap<String, String>> {
+    let (_, fragment) = url.split_once('#')?;
+
+    let params = url::form_urlencoded::parse(fragment.as_bytes())
+        .into_owned()
+        .collect::<HashMap<_, _>>();
+
+    params.contains_key("card").then_some(params)
+}
+
+/// Whether the domain is the site or one of its subdomains, `notyuque.com` is not `yuque.com`.
+pub fn is_domain(domain: &str, site: &str) -> bool {
+    domain
+        .strip_suffix(site)
+        .is_some_and(|sub| sub.is_empty() || sub.ends_with('.'))
+}
+
+/// Key of the document in the anchors, the paths may start with `./`.
+pub fn anchor_key(path: &str) -> String {
+    path.trim_start_matches("./").to_string()
+}
+
+/// Create the transform from the `options` in `config.yml`.
+pub type TransformFactory =
+    Box<dyn Fn(&serde_yaml::Value) -> Result<Box<dyn Transform>> + Send + Sync>;
+
+pub struct TransformRegistry {
+    factories: HashMap<String, TransformFactory>,
+}
+
+impl TransformRegistry {
+    /// Registry with the built-in transforms, whose options default to the generator config.
+    pub fn new(image: ImageConfig, sanitize: SanitizeConfig, embeds: EmbedRegistry) -> Self {
+        let mut registry = Self {
+            factories: HashMap::new(),
+        };
+
+        let embeds = Arc::new(embeds);
+
+        registry.register("sanitize_html", move |options| {
+            let config = options_or(options, &sanitize)?;
+            Ok(Box::new(SanitizeTransform::new(config)))
+        });
+        registry.register("callout", |options| {
+            let options = options_or(options, &CalloutOptions::default())?;
+            Ok(Box::new(CalloutTransform::new(options)))
+        });
+        registry.register("anchor", |_| Ok(Box::new(AnchorTransform::new())));
+        registry.register("math", |_| Ok(Box::new(MathTransform)));
+        registry.register("diagram", |options| {
+            let options = options_or(options, &DiagramOptions::default())?;
+            Ok(Box::new(DiagramTransform::new(options)))
+        });
+        registry.register("convert_image", move |options| {
+            let config = options_or(options, &image)?.check()?;
+            Ok(Box::new(ImageTransform::new(config)))
+        });
+        registry.register("attachment", |options| {
+            let options = options_or(options, &AttachmentOptions::default())?;
+            Ok(Box::new(AttachmentTransform::new(options)))
+        });
+        registry.register("embed", move |_| {
+            Ok(Box::new(EmbedTransform::new(embeds.clone())))
+        });
+        registry.register("convert_link", |_| Ok(Box::new(LinkTransform)));
+
+        registry
+    }
+
+    /// Register a transform, the built-in one with the same name is replaced.
+    pub fn register<F>(&mut self, name: impl Into<String>, factory: F)
+    where
+        F: Fn(&serde_yaml::Value) -> Result<Box<dyn Transform>> + Send + Sync + 'static,
+    {
+        self.factories.insert(name.into(), Box::new(factory));
+    }
+
+    /// Create the enabled transforms in order, [`DEFAULT_TRANSFORMS`] if not configured.
+    pub fn build(&self, configs: Option<&[TransformConfig]>) -> Result<Vec<Box<dyn Transform>>> {
+        let Some(configs) = configs else {
+            return DEFAULT_TRANSFORMS
+                .iter()
+                .map(|name| self.create(name, &serde_yaml::Value::Null))
+                .collect();
+        };
+
+        configs
+            .iter()
+            .filter(|config| config.enabled)
+            .map(|config| self.create(&config.name, &config.options))
+            .collect()
+    }
+
+    fn create(&self, name: &str, options: &serde_yaml::Value) -> Result<Box<dyn Transform>> {
+        let factory = self
+            .factories
+            .get(name)
+            .ok_or_else(|| Error::CantParse(format!("unknown transform `{}`", name)))?;
+
+        factory(options)
+    }
+}
+
+/// Deserialize the options merged onto the default, so the options not given are kept.
+fn options_or<T: Serialize + DeserializeOwned + Clone>(
+    options: &serde_yaml::Value,
+    default: &T,
+) -> Result<T> {
+    if options.is_null() {
+        return Ok(default.clone());
+    }
+
+    let mut merged = serde_yaml::to_value(default)?;
+    merge(&mut merged, options.clone());
+
+    Ok(serde_yaml::from_value(merged)?)
+}
+
+/// Merge the mappings recursively, the other values are replaced.
+fn merge(base: &mut serde_yaml::Value, value: serde_yaml::Value) {
+    match (base, value) {
+        (serde_yaml::Value::Mapping(base), serde_yaml::Value::Mapping(value)) => {
+            for (key, value) in value {
+                match base.get_mut(&key) {
+                    Some(base) => merge(base, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, value) => *base = value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ImageMode, OutputFormat};
+
+    #[test]
+    fn subdomains() {
+        assert!(is_domain("yuque.com", "yuque.com"));
+        assert!(is_domain("www.yuque.com", "yuque.com"));
+        assert!(!is_domain("notyuque.com", "yuque.com"));
+        assert!(!is_domain("yuque.com.evil.com", "yuque.com"));
+    }
+
+    #[test]
+    fn partial_options() {
+        let image = ImageConfig {
+            mode: ImageMode::Base64,
+            format: OutputFormat::Webp,
+            ..Default::default()
+        };
+
+        let options = serde_yaml::from_str("max_width: 800").unwrap();
+        let merged = options_or(&options, &image).unwrap();
+
+        assert_eq!(merged.max_width, Some(800));
+        assert_eq!(merged.mode, ImageMode::Base64);
+        assert_eq!(merged.format, OutputFormat::Webp);
+    }
+
+    #[test]
+    fn nested_options() {
+        let sanitize = SanitizeConfig {
+            tags: vec!["video".into()],
+            attributes: [("video".into(), vec!["src".into()])].into(),
+            ..Default::default()
+        };
+
+        let options = serde_yaml::from_str("attributes: { audio: [src] }").unwrap();
+        let merged = options_or(&options, &sanitize).unwrap();
+
+        assert!(merged.enabled);
+        assert_eq!(merged.tags, ["video"]);
+        assert_eq!(merged.attributes.len(), 2);
+    }
+}
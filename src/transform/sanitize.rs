@@ -0,0 +1,43 @@
+use comrak::nodes::{AstNode, NodeValue};
+use futures_util::future::{ready, FutureExt, LocalBoxFuture};
+
+use super::{Transform, TransformContext};
+use crate::{
+    config::SanitizeConfig,
+    error::Result,
+    sanitize::{sanitize_html, HtmlSanitizer},
+};
+
+/// Clean the raw html in the document with an allowlist.
+pub struct SanitizeTransform {
+    config: SanitizeConfig,
+}
+
+impl SanitizeTransform {
+    pub fn new(config: SanitizeConfig) -> Self {
+        Self { config }
+    }
+
+    fn sanitize<'a>(&self, node: &'a AstNode<'a>) -> Result<()> {
+        // The whole document is cleaned at once, the tags may be closed in other nodes
+        if !self.config.enabled || !matches!(node.data.borrow().value, NodeValue::Document) {
+            return Ok(());
+        }
+
+        sanitize_html(node, &HtmlSanitizer::new(&self.config))
+    }
+}
+
+impl Transform for SanitizeTransform {
+    fn name(&self) -> &str {
+        "sanitize_html"
+    }
+
+    fn transform<'a>(
+        &'a self,
+        node: &'a AstNode<'a>,
+        _: &'a TransformContext<'a>,
+    ) -> LocalBoxFuture<'a, Result<()>> {
+        ready(self.sanitize(node)).boxed_local()
+    }
+}